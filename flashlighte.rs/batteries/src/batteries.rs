@@ -33,12 +33,74 @@ impl Move {
     }
 }
 
+impl Default for Vec2 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Default for Move {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Cardinal direction a character is facing
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub enum Direction {
+    Up,
+    #[default]
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    /// Direction of a single step, `None` if the delta isn't a unit step.
+    pub fn from_delta(delta: Vec2) -> Option<Direction> {
+        match delta {
+            Vec2(0, -1) => Some(Direction::Up),
+            Vec2(0, 1) => Some(Direction::Down),
+            Vec2(-1, 0) => Some(Direction::Left),
+            Vec2(1, 0) => Some(Direction::Right),
+            _ => None,
+        }
+    }
+
+    pub fn to_delta(self) -> Vec2 {
+        match self {
+            Direction::Up => Vec2(0, -1),
+            Direction::Down => Vec2(0, 1),
+            Direction::Left => Vec2(-1, 0),
+            Direction::Right => Vec2(1, 0),
+        }
+    }
+
+    pub fn from_char(value: char) -> Option<Direction> {
+        match value {
+            'U' => Some(Direction::Up),
+            'D' => Some(Direction::Down),
+            'L' => Some(Direction::Left),
+            'R' => Some(Direction::Right),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let direction_repr = match self {
+            Direction::Up => 'U',
+            Direction::Down => 'D',
+            Direction::Left => 'L',
+            Direction::Right => 'R',
+        };
+
+        write!(f, "{direction_repr}")
+    }
+}
+
 #[wasm_bindgen]
 #[derive(PartialEq, Clone, Copy)]
 pub enum Glyph {
@@ -55,29 +117,39 @@ pub enum Glyph {
 impl Glyph {
     /// any glyph that can be used as a pathfinding target
     pub fn is_targetable(self) -> bool {
-        match self {
-            Glyph::Target
-            | Glyph::Floor
-            | Glyph::Player
-            | Glyph::Monster
-            | Glyph::DefeatedMonster => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            Glyph::Target | Glyph::Floor | Glyph::Player | Glyph::Monster | Glyph::DefeatedMonster
+        )
+    }
+}
+
+/// A character that isn't one of the map glyphs
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct InvalidGlyph(pub char);
+
+impl fmt::Display for InvalidGlyph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unexpected character {:?} found for glyph", self.0)
     }
 }
 
-impl From<u8> for Glyph {
-    fn from(value: u8) -> Self {
+impl std::error::Error for InvalidGlyph {}
+
+impl TryFrom<u8> for Glyph {
+    type Error = InvalidGlyph;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            b'X' | 0 => Glyph::Target,
-            b'_' | 1 => Glyph::Water,
-            b'T' | 2 => Glyph::Tree,
-            b'*' | 3 => Glyph::Rock,
-            b'.' | 4 => Glyph::Floor,
-            b'P' | 5 => Glyph::Player,
-            b'G' | 6 => Glyph::Monster,
-            b'g' | 7 => Glyph::DefeatedMonster,
-            _ => panic!("Unexpected character {value} found for map glyph."),
+            b'X' | 0 => Ok(Glyph::Target),
+            b'_' | 1 => Ok(Glyph::Water),
+            b'T' | 2 => Ok(Glyph::Tree),
+            b'*' | 3 => Ok(Glyph::Rock),
+            b'.' | 4 => Ok(Glyph::Floor),
+            b'P' | 5 => Ok(Glyph::Player),
+            b'G' | 6 => Ok(Glyph::Monster),
+            b'g' | 7 => Ok(Glyph::DefeatedMonster),
+            _ => Err(InvalidGlyph(value.into())),
         }
     }
 }
@@ -97,18 +169,20 @@ impl From<Glyph> for u8 {
     }
 }
 
-impl From<char> for Glyph {
-    fn from(value: char) -> Self {
+impl TryFrom<char> for Glyph {
+    type Error = InvalidGlyph;
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
         match value {
-            'X' => Glyph::Target,
-            '_' => Glyph::Water,
-            'T' => Glyph::Tree,
-            '*' => Glyph::Rock,
-            '.' => Glyph::Floor,
-            'P' => Glyph::Player,
-            'G' => Glyph::Monster,
-            'g' => Glyph::DefeatedMonster,
-            _ => panic!("Unexpected character {value} found for glyph."),
+            'X' => Ok(Glyph::Target),
+            '_' => Ok(Glyph::Water),
+            'T' => Ok(Glyph::Tree),
+            '*' => Ok(Glyph::Rock),
+            '.' => Ok(Glyph::Floor),
+            'P' => Ok(Glyph::Player),
+            'G' => Ok(Glyph::Monster),
+            'g' => Ok(Glyph::DefeatedMonster),
+            _ => Err(InvalidGlyph(value)),
         }
     }
 }
@@ -145,6 +219,14 @@ impl Glyph {
         matches!(self, Glyph::Target)
     }
 
+    /// The terrain under the glyph, characters are always standing on the floor.
+    pub fn terrain(&self) -> Glyph {
+        match self {
+            Glyph::Player | Glyph::Monster | Glyph::DefeatedMonster => Glyph::Floor,
+            _ => *self,
+        }
+    }
+
     pub fn get_legal_moves(&self) -> Vec<Vec2> {
         match self {
             Glyph::Player | Glyph::Monster => vec![
@@ -164,3 +246,11 @@ impl Glyph {
         }
     }
 }
+
+#[test]
+fn parse_glyphs() {
+    assert_eq!(Glyph::try_from('P'), Ok(Glyph::Player));
+    assert_eq!(Glyph::try_from('Z'), Err(InvalidGlyph('Z')));
+    assert_eq!(Glyph::try_from(5), Ok(Glyph::Player));
+    assert_eq!(Glyph::try_from(b'Z'), Err(InvalidGlyph('Z')));
+}
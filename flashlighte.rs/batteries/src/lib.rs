@@ -1,4 +1,5 @@
 mod batteries;
+mod transition;
 mod utils;
 
 pub mod prelude {
@@ -7,6 +8,7 @@ pub mod prelude {
     pub use wasm_bindgen::prelude::*;
 
     pub use crate::batteries::*;
+    pub use crate::transition::*;
     pub use crate::utils::*;
 }
 
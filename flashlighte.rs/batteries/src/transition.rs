@@ -0,0 +1,159 @@
+use std::cmp::Ordering;
+
+use crate::prelude::*;
+
+/// Why a change to the map was refused
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum TransitionError {
+    /// the map has a different number of cells
+    SizeChanged,
+    /// more `P`s or `G`s than before
+    CharacterSpawned(Glyph),
+    /// fewer `P`s or `G`s than before
+    CharacterRemoved(Glyph),
+    /// the cell at the index changed without a character stepping in or out of it
+    TerrainRewritten(usize),
+    /// a character ended up further than one legal step from where it was
+    IllegalStep(Glyph),
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransitionError::SizeChanged => write!(f, "map size changed"),
+            TransitionError::CharacterSpawned(glyph) => write!(f, "extra {glyph} on the map"),
+            TransitionError::CharacterRemoved(glyph) => write!(f, "{glyph} missing from the map"),
+            TransitionError::TerrainRewritten(idx) => write!(f, "terrain rewritten at {idx}"),
+            TransitionError::IllegalStep(glyph) => write!(f, "{glyph} moved more than one step"),
+        }
+    }
+}
+
+/// Checks that `next` can be reached from `previous` by characters taking legal steps.
+///
+/// Characters can only step onto empty cells and always leave floor behind,
+/// every other cell has to stay the same.
+pub fn validate_transition(
+    previous: &[Glyph],
+    next: &[Glyph],
+    width: u8,
+) -> Result<(), TransitionError> {
+    if previous.len() != next.len() {
+        return Err(TransitionError::SizeChanged);
+    }
+
+    for character in [Glyph::Player, Glyph::Monster] {
+        let before = previous.iter().filter(|glyph| **glyph == character).count();
+        let after = next.iter().filter(|glyph| **glyph == character).count();
+
+        match after.cmp(&before) {
+            Ordering::Greater => return Err(TransitionError::CharacterSpawned(character)),
+            Ordering::Less => return Err(TransitionError::CharacterRemoved(character)),
+            Ordering::Equal => {}
+        }
+    }
+
+    let mut vacated = vec![];
+    let mut entered = vec![];
+
+    for (idx, (before, after)) in previous.iter().zip(next).enumerate() {
+        if before == after {
+            continue;
+        }
+
+        let is_vacated = is_character(*before) && *after == Glyph::Floor;
+        let is_entered = is_character(*after) && before.is_empty();
+
+        match (is_vacated, is_entered) {
+            (true, _) => vacated.push((idx, *before)),
+            (false, true) => entered.push((idx, *after)),
+            (false, false) => return Err(TransitionError::TerrainRewritten(idx)),
+        }
+    }
+
+    // counts match, so every character that entered a cell has to have left one next to it
+    for (to, character) in entered {
+        let to_pos = idx_to_grid_position(to as u16, width);
+        let from = vacated.iter().position(|(from, glyph)| {
+            let from_pos = idx_to_grid_position(*from as u16, width);
+            *glyph == character
+                && character
+                    .get_legal_moves()
+                    .contains(&Vec2(to_pos.0 - from_pos.0, to_pos.1 - from_pos.1))
+        });
+
+        match from {
+            Some(from) => vacated.swap_remove(from),
+            None => return Err(TransitionError::IllegalStep(character)),
+        };
+    }
+
+    Ok(())
+}
+
+fn is_character(glyph: Glyph) -> bool {
+    matches!(glyph, Glyph::Player | Glyph::Monster)
+}
+
+#[cfg(test)]
+fn glyphs(map: &str) -> Vec<Glyph> {
+    map.bytes()
+        .map(|glyph| Glyph::try_from(glyph).unwrap())
+        .collect()
+}
+
+#[test]
+fn accept_single_steps() {
+    // . P . .
+    // G . . X
+    let previous = glyphs(".P..G..X");
+
+    assert_eq!(
+        validate_transition(&previous, &glyphs("..P.G..X"), 4),
+        Ok(())
+    );
+    assert_eq!(
+        validate_transition(&previous, &glyphs(".P...G.X"), 4),
+        Ok(())
+    );
+    assert_eq!(
+        validate_transition(&glyphs("..PX"), &glyphs("...P"), 4),
+        Ok(())
+    );
+}
+
+#[test]
+fn reject_teleports_spawns_and_rewrites() {
+    // . P . T
+    // G . . X
+    let previous = glyphs(".P.TG..X");
+
+    assert_eq!(
+        validate_transition(&previous, &glyphs(".P.TGG.X"), 4),
+        Err(TransitionError::CharacterSpawned(Glyph::Monster))
+    );
+    assert_eq!(
+        validate_transition(&previous, &glyphs("...TG.PX"), 4),
+        Err(TransitionError::IllegalStep(Glyph::Player))
+    );
+    assert_eq!(
+        validate_transition(&previous, &glyphs(".PPTG..X"), 4),
+        Err(TransitionError::CharacterSpawned(Glyph::Player))
+    );
+    assert_eq!(
+        validate_transition(&previous, &glyphs(".P..G..X"), 4),
+        Err(TransitionError::TerrainRewritten(3))
+    );
+    assert_eq!(
+        validate_transition(&previous, &glyphs("..PTG..X"), 4),
+        Ok(())
+    );
+    assert_eq!(
+        validate_transition(&glyphs(".P.T"), &glyphs("...P"), 4),
+        Err(TransitionError::TerrainRewritten(3))
+    );
+    assert_eq!(
+        validate_transition(&previous, &glyphs(".P.TG.."), 4),
+        Err(TransitionError::SizeChanged)
+    );
+}
@@ -41,22 +41,20 @@ impl Camera {
         }
     }
 
-    pub fn get_camera_view<T: Copy>(&self, map_state: &Vec<T>) -> Vec<T> {
-        return map_state
+    pub fn get_camera_view<T: Copy>(&self, map_state: &[T]) -> Vec<T> {
+        map_state
             .iter()
             .enumerate()
             .filter(|(idx, _)| {
                 let grid_pos = idx_to_grid_position(*idx as u16, self.bb_width);
 
-                return grid_pos.0 >= (self.left as i32)
+                grid_pos.0 >= (self.left as i32)
                     && grid_pos.0 < (self.width + self.left) as i32
                     && grid_pos.1 >= (self.top as i32)
-                    && grid_pos.1 < (self.height + self.top) as i32;
+                    && grid_pos.1 < (self.height + self.top) as i32
             })
-            .map(|(_, item)| {
-                return *item;
-            })
-            .collect();
+            .map(|(_, item)| *item)
+            .collect()
     }
 
     /// Convert the camera viewport position to map position
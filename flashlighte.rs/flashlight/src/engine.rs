@@ -1,6 +1,56 @@
 use js_sys::Uint8Array;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 use crate::prelude::*;
+use crate::snapshot::Snapshot;
+
+/// Battery charge of a fresh flashlight
+const MAX_BATTERY: u8 = 100;
+/// Battery charge consumed every turn the flashlight is on
+const BATTERY_DRAIN_PER_TURN: u8 = 1;
+/// Visible radius with a fully charged flashlight
+const MAX_FLASHLIGHT_RADIUS: i32 = 8;
+/// Visible radius without the flashlight, covers the adjacent cells
+const AMBIENT_LIGHT_RADIUS: i32 = 2;
+/// Spread of the flashlight beam in degrees
+const DEFAULT_CONE_ANGLE: u16 = 90;
+/// Visible radius of the monster, it doesn't need a flashlight
+const MONSTER_SIGHT_RADIUS: i32 = 6;
+
+/// Key of a player's cell in the shared player state
+fn player_cell_key(player_index: u8) -> String {
+    format!("cell_{player_index}")
+}
+
+/// Key of a player's facing in the shared player state
+fn player_facing_key(player_index: u8) -> String {
+    format!("facing_{player_index}")
+}
+
+/// Key of a player's battery charge in the shared player state
+fn player_battery_key(player_index: u8) -> String {
+    format!("battery_{player_index}")
+}
+
+/// Key of whether a player's flashlight is on in the shared player state
+fn player_light_key(player_index: u8) -> String {
+    format!("light_{player_index}")
+}
+
+/// Key of who moves the monster in the shared player state
+const MONSTER_CONTROL_KEY: &str = "monster_control";
+
+#[cfg(test)]
+thread_local! {
+    /// state vectors engines would have sent to other players
+    static SENT_STATE_VECTORS: std::cell::RefCell<Vec<Vec<u8>>> = const { std::cell::RefCell::new(vec![]) };
+}
+
+#[cfg(test)]
+fn take_sent_state_vectors() -> Vec<Vec<u8>> {
+    SENT_STATE_VECTORS.take()
+}
 
 #[cfg(test)]
 #[derive(PartialEq)]
@@ -12,7 +62,9 @@ pub struct MapState {
 impl From<&str> for MapState {
     fn from(value: &str) -> Self {
         let glyph_bytes = value.bytes();
-        let state: Vec<Glyph> = glyph_bytes.map(|glyph_byte| glyph_byte.into()).collect();
+        let state: Vec<Glyph> = glyph_bytes
+            .map(|glyph_byte| Glyph::try_from(glyph_byte).unwrap())
+            .collect();
 
         MapState { state }
     }
@@ -22,6 +74,7 @@ impl From<&str> for MapState {
 pub struct MapMetadata {
     pub target_cell_idx: u16,
     pub player_cell_idx: i32,
+    pub monster_cell_idx: i32,
     pub width: u8,
 }
 
@@ -29,32 +82,134 @@ pub struct MapMetadata {
 #[derive(Debug, PartialEq)]
 pub enum MoveOutcome {
     NoOp,
-    Rejected,
     Advance,
+    /// the character stepped towards an occupied cell and stayed put, attacks included
+    Bumped,
     End,
 }
 
+/// Role of the peer running the engine, assigned by the signaling server
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum Role {
+    /// moves a player, the first one also runs the monster
+    Player,
+    /// follows along using the deltas shared by the player
+    Spectator,
+}
+
+/// What the spectator gets to see
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum SpectatorView {
+    /// every tile on the map
+    FullMap,
+    /// tiles the monster can see
+    Monster,
+}
+
+/// Who moves the monster
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum MonsterControl {
+    /// the first player's engine chases the players with `do_move_enemy`
+    Ai,
+    /// the spectator plays the monster with `do_move_monster`
+    Spectator,
+}
+
+impl MonsterControl {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MonsterControl::Ai => "Ai",
+            MonsterControl::Spectator => "Spectator",
+        }
+    }
+
+    fn from_str(value: &str) -> Option<MonsterControl> {
+        match value {
+            "Ai" => Some(MonsterControl::Ai),
+            "Spectator" => Some(MonsterControl::Spectator),
+            _ => None,
+        }
+    }
+}
+
+/// Shape of the area lit by the flashlight
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum VisibilityMode {
+    /// lights every direction around the player
+    Omnidirectional,
+    /// lights a cone in the direction the player is facing, plus ambient light
+    Cone,
+}
+
+/// How much of a tile the player knows about
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum TileVisibility {
+    /// currently lit, terrain and characters are shown
+    Visible,
+    /// seen before, only the terrain is shown
+    Remembered,
+    /// never seen
+    Unknown,
+    /// shown in full but no flashlight reaches it, only on the spectator's full map
+    Unlit,
+}
+
 #[wasm_bindgen]
 pub struct Flashlight {
+    pub role: Role,
+    /// only used when the role is `Role::Spectator`
+    pub spectator_view: SpectatorView,
     pub width: u8,
     height: u8,
     pub view_width: u8,
     map_state_doc: Doc,
     camera: Camera,
     visibility_state: HashMap<IVec2, i32>,
+    /// tiles the players' flashlights reach, only kept for the spectator's full map
+    player_light: HashSet<IVec2>,
+    /// tiles that have been visible at least once, indexed like the map
+    explored_tiles: Vec<bool>,
     cell_width: u8,
     monster_cell: Vec2,
     target_cell: Vec2,
-    player_cell: Vec2,
+    /// one cell per `P` on the map, in the order they appear on the starting map
+    player_cells: Vec<Vec2>,
+    /// the `P` controlled by this peer, only used when the role is `Role::Player`
+    player_index: u8,
     pub player_poise: u8,
     pub monster_poise: u8,
+    pub visibility_mode: VisibilityMode,
+    /// spread of the flashlight beam in degrees, only used in `VisibilityMode::Cone`
+    pub cone_angle: u16,
+    /// turns taken by this peer's player, bumping into things included
+    turn: u32,
 }
 
 #[wasm_bindgen]
 impl Flashlight {
-    pub fn new(level: Vec<Glyph>, width: u8, cell_width: u8, view_width: u8) -> Self {
+    pub fn new(level: Vec<Glyph>, width: u8, cell_width: u8, view_width: u8, role: Role) -> Self {
+        Self::new_with_player_index(level, width, cell_width, view_width, role, 0)
+    }
+
+    /// Creates an engine for a session with more than one player.
+    ///
+    /// Every player controls the `P` at `player_index`, counting `P`s in map order.
+    /// The first player's map is the source of truth for everyone else.
+    pub fn new_with_player_index(
+        level: Vec<Glyph>,
+        width: u8,
+        cell_width: u8,
+        view_width: u8,
+        role: Role,
+        player_index: u8,
+    ) -> Self {
         let mut target_cell = Vec2::new();
-        let mut player_cell = Vec2::new();
+        let mut player_cells = vec![];
         let mut monster_cell = Vec2::new();
 
         for (idx, char) in level.iter().enumerate() {
@@ -63,7 +218,7 @@ impl Flashlight {
                 target_cell = idx_to_grid_position(idx as u16, width);
             }
             if char_glyph == Glyph::Player {
-                player_cell = idx_to_grid_position(idx as u16, width);
+                player_cells.push(idx_to_grid_position(idx as u16, width));
             }
             if char_glyph == Glyph::Monster {
                 monster_cell = idx_to_grid_position(idx as u16, width);
@@ -73,10 +228,15 @@ impl Flashlight {
         // make sure camera view includes the player
         // assumes 1:1 aspect ratio
         let mut camera = Camera::new_with_data(0, 0, view_width, view_width, width, width);
+        let player_cell = player_cells
+            .get(player_index as usize)
+            .copied()
+            .unwrap_or_default();
         camera.pan_camera_at(&player_cell);
 
         let map_state_doc = Doc::new();
         let map_state = map_state_doc.get_or_insert_text("map_state");
+        let player_state = map_state_doc.get_or_insert_map("player_state");
         // need to drop the transaction to give up the exclusive borrow
         {
             let mut txn = map_state_doc.transact_mut();
@@ -86,31 +246,55 @@ impl Flashlight {
                 .collect::<Vec<String>>()
                 .join("");
             map_state.insert(&mut txn, 0, &level_str);
+            player_state.insert(&mut txn, MONSTER_CONTROL_KEY, MonsterControl::Ai.as_str());
+            for (idx, player_cell) in player_cells.iter().enumerate() {
+                player_state.insert(
+                    &mut txn,
+                    player_cell_key(idx as u8),
+                    grid_position_to_idx(*player_cell, width),
+                );
+                player_state.insert(
+                    &mut txn,
+                    player_facing_key(idx as u8),
+                    Direction::default().to_string(),
+                );
+                player_state.insert(
+                    &mut txn,
+                    player_battery_key(idx as u8),
+                    u16::from(MAX_BATTERY),
+                );
+                player_state.insert(&mut txn, player_light_key(idx as u8), true);
+            }
 
             txn.commit();
         }
 
-        let initial_state_vector = {
-            let txn = map_state_doc.transact();
-            txn.encode_state_as_update_v1(&yrs::StateVector::default())
-        };
-        Flashlight::send_state_vector(initial_state_vector);
-
-        Self {
+        let flashlight = Self {
+            role,
+            spectator_view: SpectatorView::FullMap,
             width,
             view_width,
             // level.len == 256, which is > u8_MAX so need casting
             height: (level.len() / (width as usize)) as u8,
             visibility_state: HashMap::new(),
+            player_light: HashSet::new(),
+            explored_tiles: vec![false; level.len()],
             cell_width,
             map_state_doc,
             camera,
             monster_cell,
             target_cell,
-            player_cell,
+            player_cells,
+            player_index,
             player_poise: 100,
             monster_poise: 120,
-        }
+            visibility_mode: VisibilityMode::Omnidirectional,
+            cone_angle: DEFAULT_CONE_ANGLE,
+            turn: 0,
+        };
+        flashlight.share_state_vector();
+
+        flashlight
     }
 
     /// This function creates a new `Flashlight` instance from a JavaScript `Uint8Array`.
@@ -122,37 +306,42 @@ impl Flashlight {
         width: u8,
         cell_width: u8,
         view_width: u8,
-    ) -> Self {
+        role: Role,
+        player_index: u8,
+    ) -> Result<Flashlight, FlashlightError> {
         #[cfg(debug_assertions)]
         console_error_panic_hook::set_once();
 
         let level: Vec<u8> = level.to_vec();
-        let level: Vec<Glyph> = level.iter().map(|char| (*char).into()).collect();
+        let level = level
+            .iter()
+            .map(|char| Glyph::try_from(*char))
+            .collect::<Result<Vec<Glyph>, _>>()?;
 
-        Self::new(level, width, cell_width, view_width)
+        Ok(Self::new_with_player_index(
+            level,
+            width,
+            cell_width,
+            view_width,
+            role,
+            player_index,
+        ))
     }
 
     /// This function returns the current state of the map (after applying visibility mask and camera clipping) as a Vector of `Glyph`s.
+    ///
+    /// Remembered tiles only show their terrain, and unknown tiles show up as floor.
+    /// Use `get_clipped_tile_visibility` to tell them apart.
     pub fn get_clipped_map_state(&self) -> Vec<Glyph> {
         let glyphs = self.get_map_glyphs();
 
-        let visible_map_state = glyphs
+        let visible_map_state: Vec<Glyph> = glyphs
             .iter()
             .enumerate()
-            .map(|(idx, glyph)| {
-                let Vec2(x, y) = idx_to_grid_position(idx as u16, self.width);
-                let pos = IVec2 { x, y };
-                match self.visibility_state.contains_key(&pos) {
-                    true => match *glyph == Glyph::Monster {
-                        true => match self.monster_poise > 0 {
-                            true => *glyph,
-                            false => Glyph::DefeatedMonster,
-                        },
-                        false => *glyph,
-                    },
-                    // default to returning tree if glyph is invisible
-                    false => Glyph::Tree,
-                }
+            .map(|(idx, glyph)| match self.tile_visibility(idx) {
+                TileVisibility::Visible | TileVisibility::Unlit => self.visible_glyph(*glyph),
+                TileVisibility::Remembered => glyph.terrain(),
+                TileVisibility::Unknown => Glyph::Floor,
             })
             .collect();
 
@@ -160,12 +349,23 @@ impl Flashlight {
         self.camera.get_camera_view(&visible_map_state)
     }
 
+    /// This function returns the visibility of every tile in the camera view.
+    pub fn get_clipped_tile_visibility(&self) -> Vec<TileVisibility> {
+        let map_length = self.height as usize * self.width as usize;
+        let tile_visibility: Vec<TileVisibility> = (0..map_length)
+            .map(|idx| self.tile_visibility(idx))
+            .collect();
+
+        self.camera.get_camera_view(&tile_visibility)
+    }
+
     /// This function returns the metadata of the current map state.
     #[wasm_bindgen(getter)]
     pub fn map_metadata(&self) -> MapMetadata {
         MapMetadata {
             target_cell_idx: self.grid_position_to_idx(self.target_cell),
-            player_cell_idx: self.grid_position_to_idx(self.player_cell) as i32,
+            player_cell_idx: self.grid_position_to_idx(self.player_cell()) as i32,
+            monster_cell_idx: self.grid_position_to_idx(self.monster_cell) as i32,
             width: self.width,
         }
     }
@@ -179,10 +379,10 @@ impl Flashlight {
         let map_length = self.height as usize * self.width as usize;
         let mut visibility_state = vec![-1; map_length];
 
-        for idx in 0..map_length {
+        for (idx, visibility) in visibility_state.iter_mut().enumerate() {
             let Vec2(x, y) = idx_to_grid_position(idx as u16, self.width);
             let pos = IVec2 { x, y };
-            visibility_state[idx] = match self.visibility_state.contains_key(&pos) {
+            *visibility = match self.visibility_state.contains_key(&pos) {
                 true => *self.visibility_state.get(&pos).unwrap(),
                 false => i32::MAX,
             };
@@ -192,7 +392,168 @@ impl Flashlight {
         js_sys::Int32Array::from(&camera_view[..])
     }
 
+    /// Remaining battery charge, between 0 and `MAX_BATTERY`.
+    #[wasm_bindgen(getter)]
+    pub fn battery(&self) -> u8 {
+        self.battery_of(self.player_index)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn is_flashlight_on(&self) -> bool {
+        self.is_light_on_of(self.player_index)
+    }
+
+    /// Switches the flashlight on or off and returns the updated state.
+    ///
+    /// Switching it off saves the battery and shrinks the visible area to ambient light,
+    /// which also hides the player from the monster.
+    /// Shared with other players, so their view and the monster see the same light.
+    pub fn toggle_flashlight(&mut self) -> bool {
+        if self.role != Role::Player {
+            return self.is_flashlight_on();
+        }
+
+        let is_flashlight_on = !self.is_flashlight_on();
+        self.set_player_state(player_light_key(self.player_index), is_flashlight_on.into());
+        is_flashlight_on
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn turn(&self) -> u32 {
+        self.turn
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn player_index(&self) -> u8 {
+        self.player_index
+    }
+
+    /// Number of players on the map.
+    #[wasm_bindgen(getter)]
+    pub fn player_count(&self) -> u8 {
+        self.player_cells.len() as u8
+    }
+
+    /// Direction the player is facing, derived from their last move.
+    ///
+    /// Stored in the shared doc so that peers light the same cone.
+    #[wasm_bindgen(getter)]
+    pub fn player_facing(&self) -> Direction {
+        self.facing_of(self.player_index)
+    }
+
+    /// Who moves the monster, the same for every peer.
+    #[wasm_bindgen(getter)]
+    pub fn monster_control(&self) -> MonsterControl {
+        monster_control(&self.map_state_doc)
+    }
+
+    /// Hands the monster to the spectator or back to the AI, and shares it with other players.
+    ///
+    /// Only the first player runs the AI, so only they get to decide,
+    /// handing it back to the AI when the monster player disconnects.
+    pub fn set_monster_control(
+        &mut self,
+        monster_control: MonsterControl,
+    ) -> Result<(), FlashlightError> {
+        if !self.is_source_of_truth() {
+            return Err(FlashlightError::NotYourTurn);
+        }
+
+        if self.monster_control() == monster_control {
+            return Ok(());
+        }
+
+        let player_state = self.map_state_doc.get_or_insert_map("player_state");
+        let mut txn = self.map_state_doc.transact_mut();
+        player_state.insert(&mut txn, MONSTER_CONTROL_KEY, monster_control.as_str());
+
+        let update = txn.encode_update_v1();
+        txn.commit();
+
+        self.send_delta(update);
+
+        Ok(())
+    }
+
+    /// Computes the visible tiles for the role running the engine.
+    ///
+    /// The player sees what the flashlight lights up, the spectator sees
+    /// either the full map or what the monster sees.
     pub fn compute_visibility(&mut self) {
+        let visible_tiles_hashmap = match (self.role, self.effective_spectator_view()) {
+            (Role::Player, _) => self.compute_player_visibility(self.player_index),
+            (Role::Spectator, SpectatorView::FullMap) => {
+                self.player_light = self.compute_players_light();
+                self.compute_full_map_visibility()
+            }
+            (Role::Spectator, SpectatorView::Monster) => {
+                self.shadowcast(self.monster_cell, MONSTER_SIGHT_RADIUS)
+            }
+        };
+
+        for tile in visible_tiles_hashmap.keys() {
+            let pos = Vec2(tile.x, tile.y);
+            if self.is_in_bounds(pos) {
+                let idx = self.grid_position_to_idx(pos) as usize;
+                self.explored_tiles[idx] = true;
+            }
+        }
+
+        self.visibility_state = visible_tiles_hashmap;
+    }
+
+    /// Tiles lit by the flashlight of the player at `player_index`.
+    fn compute_player_visibility(&self, player_index: u8) -> HashMap<IVec2, i32> {
+        let player_cell = self
+            .player_cells
+            .get(player_index as usize)
+            .copied()
+            .unwrap_or_default();
+        let mut visible_tiles_hashmap =
+            self.shadowcast(player_cell, self.flashlight_radius(player_index));
+
+        if self.visibility_mode == VisibilityMode::Cone {
+            let observer = IVec2 {
+                x: player_cell.0,
+                y: player_cell.1,
+            };
+            // synced through the doc, so spectators light the same cone
+            let facing = self.facing_of(player_index);
+            visible_tiles_hashmap
+                .retain(|tile, _| self.is_in_flashlight_cone(observer, *tile, facing));
+        }
+
+        visible_tiles_hashmap
+    }
+
+    /// Tiles lit by any player's flashlight.
+    fn compute_players_light(&self) -> HashSet<IVec2> {
+        (0..self.player_cells.len() as u8)
+            .flat_map(|idx| self.compute_player_visibility(idx).into_keys())
+            .collect()
+    }
+
+    /// Every tile on the map, with distances measured from the player.
+    fn compute_full_map_visibility(&self) -> HashMap<IVec2, i32> {
+        let player_cell = self.player_cell();
+        let player = IVec2 {
+            x: player_cell.0,
+            y: player_cell.1,
+        };
+        let map_length = self.height as usize * self.width as usize;
+
+        (0..map_length)
+            .map(|idx| {
+                let Vec2(x, y) = idx_to_grid_position(idx as u16, self.width);
+                let pos = IVec2 { x, y };
+                (pos, player.distance_squared(pos))
+            })
+            .collect()
+    }
+
+    /// Tiles visible from `observer` within `radius`, opaque glyphs block the view.
+    fn shadowcast(&self, observer: Vec2, radius: i32) -> HashMap<IVec2, i32> {
         let glyphs = self.get_map_glyphs();
 
         let tiles = glyphs
@@ -212,18 +573,204 @@ impl Flashlight {
             cols: self.height as i32,
             cell_width: self.cell_width as i32,
         };
-        let mut visibility: Visibility = Visibility::new(world_dimensions, false, 8);
+        let mut visibility: Visibility = Visibility::new(world_dimensions, false, radius);
 
         visibility.observer = IVec2 {
-            x: self.player_cell.0,
-            y: self.player_cell.1,
+            x: observer.0,
+            y: observer.1,
         };
-        let visible_tiles_hashmap = visibility.compute_visible_tiles(&TileGrid {
+        visibility.compute_visible_tiles(&TileGrid {
             tiles,
             grid_dimensions: world_dimensions,
-        });
+        })
+    }
 
-        self.visibility_state = visible_tiles_hashmap;
+    /// The monster player only gets to see what the monster sees.
+    fn effective_spectator_view(&self) -> SpectatorView {
+        match self.monster_control() {
+            MonsterControl::Spectator => SpectatorView::Monster,
+            MonsterControl::Ai => self.spectator_view,
+        }
+    }
+
+    /// The cell the camera follows.
+    fn focus_cell(&self) -> Vec2 {
+        match (self.role, self.effective_spectator_view()) {
+            (Role::Spectator, SpectatorView::Monster) => self.monster_cell,
+            (_, _) => self.player_cell(),
+        }
+    }
+
+    /// The cell of the `P` controlled by this peer.
+    fn player_cell(&self) -> Vec2 {
+        self.player_cells
+            .get(self.player_index as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Direction the player at `player_index` is facing.
+    fn facing_of(&self, player_index: u8) -> Direction {
+        let player_state = self.map_state_doc.get_or_insert_map("player_state");
+        let txn = self.map_state_doc.transact();
+
+        match player_state.get(&txn, &player_facing_key(player_index)) {
+            Some(Out::Any(Any::String(facing))) => facing
+                .chars()
+                .next()
+                .and_then(Direction::from_char)
+                .unwrap_or_default(),
+            _ => Direction::default(),
+        }
+    }
+
+    /// Battery charge of the player at `player_index`.
+    fn battery_of(&self, player_index: u8) -> u8 {
+        battery(&self.map_state_doc, player_index)
+    }
+
+    /// Whether the flashlight of the player at `player_index` is on, as it starts.
+    fn is_light_on_of(&self, player_index: u8) -> bool {
+        let player_state = self.map_state_doc.get_or_insert_map("player_state");
+        let txn = self.map_state_doc.transact();
+
+        match player_state.get(&txn, &player_light_key(player_index)) {
+            Some(Out::Any(Any::Bool(is_light_on))) => is_light_on,
+            _ => true,
+        }
+    }
+
+    /// Visibility of the tile at `idx` based on the current and explored tiles.
+    fn tile_visibility(&self, idx: usize) -> TileVisibility {
+        let Vec2(x, y) = idx_to_grid_position(idx as u16, self.width);
+
+        // the whole map is shown, lit where the players' flashlights reach
+        if (self.role, self.effective_spectator_view()) == (Role::Spectator, SpectatorView::FullMap)
+        {
+            return match self.player_light.contains(&IVec2 { x, y }) {
+                true => TileVisibility::Visible,
+                false => TileVisibility::Unlit,
+            };
+        }
+
+        if self.visibility_state.contains_key(&IVec2 { x, y }) {
+            return TileVisibility::Visible;
+        }
+
+        match self.explored_tiles.get(idx) {
+            Some(true) => TileVisibility::Remembered,
+            _ => TileVisibility::Unknown,
+        }
+    }
+
+    /// The glyph as it appears when lit.
+    fn visible_glyph(&self, glyph: Glyph) -> Glyph {
+        match glyph == Glyph::Monster && self.monster_poise == 0 {
+            true => Glyph::DefeatedMonster,
+            false => glyph,
+        }
+    }
+
+    /// The map as the monster sees it, it can only find its way through tiles
+    /// any player's flashlight lights up.
+    fn get_lit_map_state(&self) -> Vec<Glyph> {
+        let glyphs = self.get_map_glyphs();
+        let players_light = self.compute_players_light();
+
+        let lit_map_state: Vec<Glyph> = glyphs
+            .iter()
+            .enumerate()
+            .map(|(idx, glyph)| {
+                let Vec2(x, y) = idx_to_grid_position(idx as u16, self.width);
+                match players_light.contains(&IVec2 { x, y }) {
+                    true => self.visible_glyph(*glyph),
+                    // anything in the dark blocks the way
+                    false => Glyph::Tree,
+                }
+            })
+            .collect();
+
+        self.camera.get_camera_view(&lit_map_state)
+    }
+
+    /// Visible radius of the player at `player_index` based on their remaining battery.
+    ///
+    /// The radius shrinks linearly with the charge and never drops below ambient light.
+    fn flashlight_radius(&self, player_index: u8) -> i32 {
+        if !self.is_light_on_of(player_index) {
+            return AMBIENT_LIGHT_RADIUS;
+        }
+
+        let charge = self.battery_of(player_index) as i32;
+        let max_charge = MAX_BATTERY as i32;
+        let boost = (MAX_FLASHLIGHT_RADIUS - AMBIENT_LIGHT_RADIUS) * charge;
+
+        // round up so that any charge left lights at least one extra cell
+        AMBIENT_LIGHT_RADIUS + (boost + max_charge - 1) / max_charge
+    }
+
+    /// Checks whether a tile is lit when the flashlight is pointed towards `facing`.
+    ///
+    /// Tiles within ambient light are always lit.
+    fn is_in_flashlight_cone(&self, observer: IVec2, tile: IVec2, facing: Direction) -> bool {
+        if observer.distance_squared(tile) < AMBIENT_LIGHT_RADIUS.pow(2) {
+            return true;
+        }
+
+        let Vec2(x, y) = facing.to_delta();
+        let facing = IVec2 { x, y }.as_vec2();
+        let offset = (tile - observer).as_vec2();
+        let half_angle = (self.cone_angle as f32 / 2.).to_radians();
+
+        // angle between the beam and the tile is within half the spread
+        facing.dot(offset) >= offset.length() * half_angle.cos()
+    }
+
+    /// Updates the direction the player is facing and shares it with other players.
+    fn set_player_facing(&mut self, facing: Direction) {
+        if self.player_facing() == facing {
+            return;
+        }
+
+        self.set_player_state(
+            player_facing_key(self.player_index),
+            facing.to_string().into(),
+        );
+    }
+
+    /// Drains the battery by a turn's worth of charge if the flashlight is on.
+    fn drain_battery(&mut self) {
+        let battery = self.battery();
+        if self.is_flashlight_on() && battery > 0 {
+            let battery = battery.saturating_sub(BATTERY_DRAIN_PER_TURN);
+            self.set_player_state(
+                player_battery_key(self.player_index),
+                u16::from(battery).into(),
+            );
+        }
+    }
+
+    /// Writes `key` to the shared player state and shares it with other players.
+    fn set_player_state(&mut self, key: String, value: Any) {
+        let player_state = self.map_state_doc.get_or_insert_map("player_state");
+        let mut txn = self.map_state_doc.transact_mut();
+        player_state.insert(&mut txn, key, value);
+
+        let update = txn.encode_update_v1();
+        txn.commit();
+
+        self.send_delta(update);
+    }
+
+    /// Fraction of the map lit by the flashlight.
+    ///
+    /// With the flashlight off the player is only exposed to ambient light.
+    fn exposure_fraction(&self) -> f32 {
+        if !self.is_flashlight_on() {
+            return 0.;
+        }
+
+        self.visibility_state.len() as f32 / (self.width as f32 * self.height as f32)
     }
 
     /// Just a wrapper for binding the width argument.
@@ -240,21 +787,65 @@ impl Flashlight {
     }
 
     /// Sends the initial state vector to other players
-    #[allow(unused_variables)]
     fn send_state_vector(state_vector: Vec<u8>) {
         #[cfg(not(test))]
         crate::send_initial_state_vector(&state_vector[..]);
+        #[cfg(test)]
+        SENT_STATE_VECTORS.with_borrow_mut(|sent| sent.push(state_vector));
+    }
+
+    /// Switches the role of the peer running the engine, when a spectator takes over a player's slot.
+    ///
+    /// The doc is kept, so the game carries on from where the peer was following it.
+    pub fn set_role(&mut self, role: Role, player_index: u8) {
+        self.role = role;
+        self.player_index = player_index;
+
+        let focus_cell = self.focus_cell();
+        self.camera.pan_camera_at(&focus_cell);
+
+        // taking over the first slot makes this map the source of truth
+        self.share_state_vector();
+    }
+
+    /// Sends the whole map to other players, who replace theirs with it.
+    ///
+    /// Only the first player's map is the source of truth, nobody else sends anything.
+    /// Also used to bring back peers that refused a delta and fell out of sync.
+    pub fn share_state_vector(&self) {
+        if self.is_source_of_truth() {
+            Flashlight::send_state_vector(self.encode_doc());
+        }
     }
 
-    pub fn apply_initial_state_vector_js(&mut self, state_vector: Uint8Array) {
-        self.apply_initial_state_vector(&state_vector.to_vec());
+    /// The first player shares their map and runs the monster for everyone.
+    fn is_source_of_truth(&self) -> bool {
+        self.role == Role::Player && self.player_index == 0
+    }
+
+    /// The shared doc as a single update.
+    fn encode_doc(&self) -> Vec<u8> {
+        self.map_state_doc
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default())
+    }
+
+    pub fn apply_initial_state_vector_js(
+        &mut self,
+        state_vector: Uint8Array,
+    ) -> Result<(), FlashlightError> {
+        self.apply_initial_state_vector(&state_vector.to_vec())
     }
 
     /// Applies the initial state vector received from the player
-    fn apply_initial_state_vector(&mut self, state_vector: &[u8]) {
+    ///
+    /// The current map is kept when the state vector doesn't hold a readable map,
+    /// or when players in it aren't standing on a `P`.
+    /// The first player's map is the source of truth, so they ignore state vectors.
+    fn apply_initial_state_vector(&mut self, state_vector: &[u8]) -> Result<(), FlashlightError> {
         // Check for empty state vector
-        if state_vector.is_empty() {
-            return;
+        if state_vector.is_empty() || self.is_source_of_truth() {
+            return Ok(());
         }
 
         // Create a new doc and apply the state vector to it
@@ -262,100 +853,276 @@ impl Flashlight {
 
         {
             let mut txn = new_map_state_doc.transact_mut();
-            match Update::decode_v1(state_vector) {
-                Ok(u) => {
-                    let _ = txn.apply_update(u);
-                    txn.commit();
-                }
-                Err(_) => {
-                    // could not decode state vector
-                    return;
-                }
-            }
+            let update =
+                Update::decode_v1(state_vector).map_err(|_| FlashlightError::DecodeFailed)?;
+            txn.apply_update(update)
+                .map_err(|_| FlashlightError::DecodeFailed)?;
+            txn.commit();
         }
 
+        let glyphs = map_glyphs(&new_map_state_doc)?;
+        // may be many turns ahead, so batteries aren't compared with ours
+        validate_player_state(&new_map_state_doc, &glyphs, None)?;
+
         // Replace the existing map_state_doc with the new one
         self.map_state_doc = new_map_state_doc;
+        // can arrive mid-game when resyncing
+        self.reset_character_positions();
+
+        Ok(())
     }
 
-    pub fn apply_delta_js(&mut self, delta: Uint8Array) {
-        self.apply_delta(&delta.to_vec());
+    /// Applies a delta from the peer the signaling server gave `sender_role` and `sender_index`.
+    pub fn apply_delta_js(
+        &mut self,
+        delta: Uint8Array,
+        sender_role: Role,
+        sender_index: u8,
+    ) -> Result<(), FlashlightError> {
+        self.apply_delta(&delta.to_vec(), sender_role, sender_index)
     }
 
     /// Applies a delta received from the other player
-    fn apply_delta(&mut self, delta: &[u8]) {
+    ///
+    /// Deltas are checked against a copy of the map first and only applied if they're valid,
+    /// and only touch what the sender controls.
+    fn apply_delta(
+        &mut self,
+        delta: &[u8],
+        sender_role: Role,
+        sender_index: u8,
+    ) -> Result<(), FlashlightError> {
         // Check for empty delta
         if delta.is_empty() {
-            return;
+            return Ok(());
         }
 
+        let decode = || Update::decode_v1(delta).map_err(|_| FlashlightError::DecodeFailed);
+        validate_update(
+            &self.map_state_doc,
+            decode()?,
+            self.width,
+            sender_role,
+            sender_index,
+        )?;
+
         {
             let mut txn = self.map_state_doc.transact_mut();
-            match Update::decode_v1(delta) {
-                Ok(u) => {
-                    let _ = txn.apply_update(u);
-                    txn.commit();
-                }
-                Err(_) => {
-                    // could not decode delta
-                    return;
-                }
-            }
+            txn.apply_update(decode()?)
+                .map_err(|_| FlashlightError::DecodeFailed)?;
+            txn.commit();
         }
 
         self.reset_character_positions();
+
+        Ok(())
     }
 
-    /// resets the character positions if they are not in sync with the map state
-    fn reset_character_positions(&mut self) {
-        let glyphs = self.get_map_glyphs();
+    /// Captures the game so it can be picked back up with `restore`, after a page reload for instance.
+    pub fn snapshot(&self) -> Vec<u8> {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            doc: self.encode_doc(),
+            role: self.role,
+            spectator_view: self.spectator_view,
+            width: self.width,
+            view_width: self.view_width,
+            cell_width: self.cell_width,
+            player_index: self.player_index,
+            player_poise: self.player_poise,
+            monster_poise: self.monster_poise,
+            visibility_mode: self.visibility_mode,
+            cone_angle: self.cone_angle,
+            explored_tiles: self.explored_tiles.clone(),
+            turn: self.turn,
+        }
+        .to_bytes()
+    }
 
-        let mut target_cell = Vec2::new();
-        let mut player_cell = Vec2::new();
-        let mut monster_cell = Vec2::new();
+    /// Recreates an engine from a `snapshot`.
+    ///
+    /// Nothing is sent to other players, peers that are still around keep syncing through deltas.
+    pub fn restore(bytes: &[u8]) -> Result<Flashlight, FlashlightError> {
+        let snapshot = Snapshot::from_bytes(bytes)?;
 
-        for (idx, glyph) in glyphs.iter().enumerate() {
-            let char_glyph: Glyph = *glyph;
+        let map_state_doc = Doc::new();
+        {
+            let mut txn = map_state_doc.transact_mut();
+            let update =
+                Update::decode_v1(&snapshot.doc).map_err(|_| FlashlightError::DecodeFailed)?;
+            txn.apply_update(update)
+                .map_err(|_| FlashlightError::DecodeFailed)?;
+            txn.commit();
+        }
+
+        let level = map_glyphs(&map_state_doc)?;
+        // checked like a state vector from a peer, the snapshot may have been tampered with
+        validate_player_state(&map_state_doc, &level, None)?;
+        let width = snapshot.width;
+        if width == 0
+            || level.len() % width as usize != 0
+            || level.len() != snapshot.explored_tiles.len()
+        {
+            return Err(FlashlightError::DecodeFailed);
+        }
+
+        // the camera can't be wider than the map, or the views would read past its edges
+        let height = u8::try_from(level.len() / width as usize)
+            .map_err(|_| FlashlightError::DecodeFailed)?;
+        let view_width = snapshot.view_width;
+        if view_width == 0 || view_width > width || view_width > height {
+            return Err(FlashlightError::DecodeFailed);
+        }
+
+        let mut flashlight = Self {
+            role: snapshot.role,
+            spectator_view: snapshot.spectator_view,
+            width,
+            view_width,
+            height,
+            visibility_state: HashMap::new(),
+            player_light: HashSet::new(),
+            explored_tiles: snapshot.explored_tiles,
+            cell_width: snapshot.cell_width,
+            map_state_doc,
+            camera: Camera::new_with_data(0, 0, view_width, view_width, width, width),
+            monster_cell: Vec2::new(),
+            target_cell: Vec2::new(),
+            player_cells: vec![],
+            player_index: snapshot.player_index,
+            player_poise: snapshot.player_poise,
+            monster_poise: snapshot.monster_poise,
+            visibility_mode: snapshot.visibility_mode,
+            cone_angle: snapshot.cone_angle,
+            turn: snapshot.turn,
+        };
+        // positions live in the doc
+        flashlight.reset_character_positions();
+
+        Ok(flashlight)
+    }
+
+    /// resets the character positions if they are not in sync with the map state
+    fn reset_character_positions(&mut self) {
+        let glyphs = self.get_map_glyphs();
+
+        let mut target_cell = Vec2::new();
+        let mut player_cells = vec![];
+        let mut monster_cell = Vec2::new();
+
+        for (idx, glyph) in glyphs.iter().enumerate() {
+            let char_glyph: Glyph = *glyph;
             if char_glyph == Glyph::Target {
                 target_cell = self.idx_to_grid_position(idx as u16);
             }
             if char_glyph == Glyph::Player {
-                player_cell = self.idx_to_grid_position(idx as u16);
+                player_cells.push(self.idx_to_grid_position(idx as u16));
             }
             if char_glyph == Glyph::Monster {
                 monster_cell = self.idx_to_grid_position(idx as u16);
             }
         }
 
+        // map order changes as players move around, the shared cells keep track of who is who
+        {
+            let player_state = self.map_state_doc.get_or_insert_map("player_state");
+            let txn = self.map_state_doc.transact();
+
+            for (player_index, player_cell) in player_cells.iter_mut().enumerate() {
+                if let Some(Out::Any(idx)) =
+                    player_state.get(&txn, &player_cell_key(player_index as u8))
+                    && let Ok(idx) = u16::try_from(idx)
+                {
+                    *player_cell = self.idx_to_grid_position(idx);
+                }
+            }
+        }
+
         self.target_cell = target_cell;
-        self.player_cell = player_cell;
+        self.player_cells = player_cells;
         self.monster_cell = monster_cell;
-        self.camera.pan_camera_at(&player_cell);
+
+        let focus_cell = self.focus_cell();
+        self.camera.pan_camera_at(&focus_cell);
     }
 
     /// This function allows the user to move the character to a new position.
     ///
-    pub fn do_move_player(&mut self, pos: Vec2) -> MoveOutcome {
+    /// Only the player gets to move the player.
+    /// Bumping into something still costs a turn, errors leave the game untouched.
+    pub fn do_move_player(&mut self, pos: Vec2) -> Result<MoveOutcome, FlashlightError> {
+        if self.role != Role::Player {
+            return Err(FlashlightError::NotYourTurn);
+        }
+
+        if self.is_end_state() {
+            return Err(FlashlightError::GameOver);
+        }
+
+        if !self.is_in_bounds(pos) {
+            return Err(FlashlightError::OutOfBounds);
+        }
+
+        let player_cell = self.player_cell();
+        if player_cell == pos {
+            return Ok(MoveOutcome::NoOp);
+        }
+
+        let facing = Direction::from_delta(Vec2(pos.0 - player_cell.0, pos.1 - player_cell.1));
+        let outcome = self.move_glyph(Move::new_with_data(player_cell, pos));
+
+        // every step costs a turn, bumps included
+        if let Ok(MoveOutcome::Advance | MoveOutcome::Bumped | MoveOutcome::End) = outcome {
+            self.turn += 1;
+            self.drain_battery();
+
+            // player turns towards the cell even if they couldn't move into it
+            if let Some(facing) = facing {
+                self.set_player_facing(facing);
+            }
+        }
+
+        outcome
+    }
+
+    /// This function allows the monster player to move the monster to a new position.
+    ///
+    /// Only available to the spectator when the monster isn't run by the AI.
+    pub fn do_move_monster(&mut self, pos: Vec2) -> Result<MoveOutcome, FlashlightError> {
+        if self.role != Role::Spectator || self.monster_control() != MonsterControl::Spectator {
+            return Err(FlashlightError::NotYourTurn);
+        }
+
         if self.is_end_state() {
-            return MoveOutcome::End;
+            return Err(FlashlightError::GameOver);
         }
 
         if !self.is_in_bounds(pos) {
-            return MoveOutcome::Rejected;
+            return Err(FlashlightError::OutOfBounds);
         }
 
-        if self.player_cell == pos {
-            return MoveOutcome::NoOp;
+        if self.monster_cell == pos {
+            return Ok(MoveOutcome::NoOp);
         }
 
-        self.move_glyph(Move::new_with_data(self.player_cell, pos))
+        self.move_glyph(Move::new_with_data(self.monster_cell, pos))
     }
 
     /// This function moves the monster towards the player.
     ///
-    pub fn do_move_enemy(&mut self) -> MoveOutcome {
+    /// The monster is run by the first player, everyone else receives its moves as deltas.
+    /// Does nothing while the spectator is playing the monster.
+    pub fn do_move_enemy(&mut self) -> Result<MoveOutcome, FlashlightError> {
+        if !self.is_source_of_truth() {
+            return Err(FlashlightError::NotYourTurn);
+        }
+
+        if self.monster_control() != MonsterControl::Ai {
+            return Ok(MoveOutcome::NoOp);
+        }
+
         let clipped_path = find_path(
-            &self.get_clipped_map_state(),
+            &self.get_lit_map_state(),
             self.camera.width,
             Glyph::Monster,
             Glyph::Player,
@@ -367,17 +1134,17 @@ impl Flashlight {
             let map_pos = self.camera.get_map_pos(&clipped_first_move.to);
 
             if !self.is_in_bounds(map_pos) {
-                return MoveOutcome::Rejected;
+                return Err(FlashlightError::OutOfBounds);
             }
 
             if self.monster_cell == map_pos {
-                return MoveOutcome::NoOp;
+                return Ok(MoveOutcome::NoOp);
             }
 
             return self.move_glyph(Move::new_with_data(self.monster_cell, map_pos));
         }
 
-        return MoveOutcome::NoOp;
+        Ok(MoveOutcome::NoOp)
     }
 
     /// This function checks whether the full map is solvable.
@@ -389,25 +1156,31 @@ impl Flashlight {
         let monster_solution = find_path(&glyphs, self.width, Glyph::Monster, Glyph::Player);
         let player_solution = find_path(&glyphs, self.width, Glyph::Player, Glyph::Target);
 
-        return monster_solution.len() > 0 && player_solution.len() > 0;
+        !monster_solution.is_empty() && !player_solution.is_empty()
     }
 
     /// Move a glyph based on the intended move.
     ///
     /// If the move is valid,
     /// it will be executed.
-    fn move_glyph(&mut self, current_move: Move) -> MoveOutcome {
+    /// Stepping towards an occupied cell is a bump, which hurts whoever bumped or got bumped into.
+    fn move_glyph(&mut self, current_move: Move) -> Result<MoveOutcome, FlashlightError> {
         if !self.is_in_bounds(current_move.to) {
-            return MoveOutcome::NoOp;
+            return Err(FlashlightError::OutOfBounds);
         }
 
         let current_glyph = self.get_glyph_at_position(current_move.from);
         let target_glyph = self.get_glyph_at_position(current_move.to);
 
-        if !self.is_move_legal(current_move) {
+        if !self.is_step_legal(current_move) {
+            return Err(FlashlightError::IllegalMove);
+        }
+
+        if let Some(target_glyph) = target_glyph
+            && !target_glyph.is_empty()
+        {
             // exposure determines how much player/monster gets damaged
-            let exposure_fraction =
-                self.visibility_state.len() as f32 / (self.width as f32 * self.height as f32);
+            let exposure_fraction = self.exposure_fraction();
 
             let player_damage = ((1.0 - exposure_fraction) * 100.).min(20.) as i32;
             let monster_damage = (exposure_fraction * 100.).min(20.) as i32;
@@ -417,35 +1190,47 @@ impl Flashlight {
             // player is more vulnerable in darkness
             match (current_glyph, target_glyph) {
                 (Some(Glyph::Player), target_glyph) => match target_glyph {
-                    Some(Glyph::Monster) => {
+                    Glyph::Monster => {
                         self.reduce_monster_poise(monster_damage);
                     }
                     _ => {
                         self.reduce_player_poise(1);
                     }
                 },
-                (Some(Glyph::Monster), Some(Glyph::Player)) => {
+                (Some(Glyph::Monster), Glyph::Player) => {
                     self.reduce_player_poise(player_damage);
                 }
                 (_, _) => {}
             };
 
-            return MoveOutcome::Rejected;
-        } else {
-            match (current_glyph, target_glyph) {
-                (Some(Glyph::Player), Some(Glyph::Target)) => {
-                    self.increase_player_poise();
-                }
-                (_, _) => {}
-            }
+            return match self.is_end_state() {
+                true => Ok(MoveOutcome::End),
+                false => Ok(MoveOutcome::Bumped),
+            };
+        } else if let (Some(Glyph::Player), Some(Glyph::Target)) = (current_glyph, target_glyph) {
+            self.increase_player_poise();
         }
 
         match current_glyph {
-            None => MoveOutcome::Rejected,
+            None => Err(FlashlightError::OutOfBounds),
             Some(current_glyph) => {
-                if self.place_glyph_at_position(current_move.to, current_glyph) {
-                    self.make_cell_empty(current_move.from);
-
+                let player_index = match current_glyph {
+                    Glyph::Player => self
+                        .player_cells
+                        .iter()
+                        .position(|player_cell| *player_cell == current_move.from)
+                        .map(|player_index| player_index as u8),
+                    _ => None,
+                };
+
+                // both cells go out in one delta, so peers never see the glyph in two places
+                if self.place_glyphs(
+                    &[
+                        (current_move.to, current_glyph),
+                        (current_move.from, Glyph::Floor),
+                    ],
+                    player_index.map(|player_index| (player_index, current_move.to)),
+                ) {
                     let outcome = match self.is_end_state() {
                         true => MoveOutcome::End,
                         false => MoveOutcome::Advance,
@@ -453,18 +1238,17 @@ impl Flashlight {
 
                     // TODO: is this check really necessary?
                     if outcome == MoveOutcome::Advance || outcome == MoveOutcome::End {
-                        if current_glyph == Glyph::Player {
-                            self.player_cell = current_move.to;
-                            self.camera.pan_camera_at(&self.player_cell);
-                        }
                         if current_glyph == Glyph::Monster {
                             self.monster_cell = current_move.to;
                         }
+
+                        let focus_cell = self.focus_cell();
+                        self.camera.pan_camera_at(&focus_cell);
                     };
 
-                    return outcome;
+                    return Ok(outcome);
                 }
-                MoveOutcome::NoOp
+                Ok(MoveOutcome::NoOp)
             }
         }
     }
@@ -477,7 +1261,7 @@ impl Flashlight {
     }
 
     fn increase_player_poise(&mut self) {
-        self.player_poise = self.player_poise + 50;
+        self.player_poise += 50;
     }
 
     fn reduce_monster_poise(&mut self, damage: i32) {
@@ -486,45 +1270,44 @@ impl Flashlight {
         self.monster_poise = updated_health.max(0) as u8;
     }
 
-    /// This function places a glyph at the specified position on the map.
-    fn place_glyph_at_position(&mut self, pos: Vec2, glyph: Glyph) -> bool {
-        if !self.is_in_bounds(pos) {
+    /// This function places glyphs at the specified positions on the map, in a single delta.
+    ///
+    /// A player moved along with the glyphs gets their shared cell updated in the same delta,
+    /// so peers can check that every player is standing on a `P`.
+    fn place_glyphs(&mut self, glyphs: &[(Vec2, Glyph)], moved_player: Option<(u8, Vec2)>) -> bool {
+        if glyphs.iter().any(|(pos, _)| !self.is_in_bounds(*pos)) {
             return false;
         }
 
-        let idx = self.grid_position_to_idx(pos);
-
         let map_state = self.map_state_doc.get_or_insert_text("map_state");
+        let player_state = self.map_state_doc.get_or_insert_map("player_state");
         let mut txn = self.map_state_doc.transact_mut();
-        map_state.remove_range(&mut txn, idx as u32, 1);
-        map_state.insert(&mut txn, idx as u32, &glyph.to_string());
+        for (pos, glyph) in glyphs {
+            let idx = self.grid_position_to_idx(*pos);
+            map_state.remove_range(&mut txn, idx as u32, 1);
+            map_state.insert(&mut txn, idx as u32, &glyph.to_string());
+        }
+        if let Some((player_index, cell)) = moved_player {
+            let idx = self.grid_position_to_idx(cell);
+            player_state.insert(&mut txn, player_cell_key(player_index), idx);
+        }
 
         let update = txn.encode_update_v1();
         txn.commit();
 
+        if let Some((player_index, cell)) = moved_player
+            && let Some(player_cell) = self.player_cells.get_mut(player_index as usize)
+        {
+            *player_cell = cell;
+        }
+
         self.send_delta(update);
 
         true
     }
 
-    fn make_cell_empty(&mut self, pos: Vec2) {
-        self.place_glyph_at_position(pos, Glyph::Floor);
-    }
-
-    /// Checks if the move is legal by verifying if the destination cell is empty
-    /// and if the move is within movable distance.
-    fn is_move_legal(&self, current_move: Move) -> bool {
-        let destination = self.get_glyph_at_position(current_move.to);
-
-        match destination {
-            None => {}
-            Some(destination) => {
-                if !destination.is_empty() {
-                    return false;
-                }
-            }
-        };
-
+    /// Checks if the move is within movable distance, whatever is on the destination cell.
+    fn is_step_legal(&self, current_move: Move) -> bool {
         let current_glyph = self.get_glyph_at_position(current_move.from);
         match current_glyph {
             None => false,
@@ -550,11 +1333,7 @@ impl Flashlight {
             None => None,
             Some(idx) => {
                 let glyphs = self.get_map_glyphs();
-                let glyph = glyphs.get(idx as usize);
-                match glyph {
-                    None => None,
-                    Some(glyph) => Some(*glyph),
-                }
+                glyphs.get(idx as usize).copied()
             }
         }
     }
@@ -577,14 +1356,229 @@ impl Flashlight {
         grid_position_to_idx(pos, self.width)
     }
 
+    /// Only valid updates make it into the doc, so every character in it is a glyph.
     fn get_map_glyphs(&self) -> Vec<Glyph> {
-        let map_state = self.map_state_doc.get_or_insert_text("map_state");
-        let txn = self.map_state_doc.transact();
-        let map_state_string = map_state.get_string(&txn);
-        let map_glyphs: Vec<Glyph> = map_state_string.chars().map(|glyph| glyph.into()).collect();
+        map_glyphs(&self.map_state_doc).unwrap_or_default()
+    }
+}
+
+fn map_glyphs(doc: &Doc) -> Result<Vec<Glyph>, InvalidGlyph> {
+    let map_state = doc.get_or_insert_text("map_state");
+    let txn = doc.transact();
+    let map_state_string = map_state.get_string(&txn);
+
+    map_state_string.chars().map(Glyph::try_from).collect()
+}
+
+/// Replays an update on a copy of `doc` and checks the map and players it leaves behind.
+///
+/// `doc` is left untouched.
+fn validate_update(
+    doc: &Doc,
+    update: Update,
+    width: u8,
+    sender_role: Role,
+    sender_index: u8,
+) -> Result<(), FlashlightError> {
+    let current_state = doc
+        .transact()
+        .encode_state_as_update_v1(&yrs::StateVector::default());
+
+    let replayed_doc = Doc::new();
+    {
+        let mut txn = replayed_doc.transact_mut();
+        let current_state =
+            Update::decode_v1(&current_state).map_err(|_| FlashlightError::DecodeFailed)?;
+        txn.apply_update(current_state)
+            .map_err(|_| FlashlightError::DecodeFailed)?;
+        txn.apply_update(update)
+            .map_err(|_| FlashlightError::DecodeFailed)?;
+        txn.commit();
+    }
+
+    let previous = map_glyphs(doc).map_err(|_| FlashlightError::InvalidGlyph)?;
+    let next = map_glyphs(&replayed_doc).map_err(|_| FlashlightError::InvalidGlyph)?;
+
+    validate_transition(&previous, &next, width).map_err(|_| FlashlightError::IllegalMove)?;
+    validate_player_state(&replayed_doc, &next, Some(doc))?;
+    validate_sender(
+        doc,
+        &replayed_doc,
+        (&previous, &next),
+        sender_role,
+        sender_index,
+    )
+}
+
+/// Checks that the sender only changed what they control.
+///
+/// Players update their own keys in the player state, the monster is moved by whoever
+/// controls it, and only the first player hands it over.
+fn validate_sender(
+    previous_doc: &Doc,
+    next_doc: &Doc,
+    (previous, next): (&[Glyph], &[Glyph]),
+    sender_role: Role,
+    sender_index: u8,
+) -> Result<(), FlashlightError> {
+    let is_source_of_truth = sender_role == Role::Player && sender_index == 0;
+    let before = player_state_entries(previous_doc);
+    let after = player_state_entries(next_doc);
+
+    let changed_keys = before
+        .keys()
+        .chain(after.keys())
+        .filter(|key| before.get(*key) != after.get(*key));
+    for key in changed_keys {
+        let is_allowed = match key.as_str() == MONSTER_CONTROL_KEY {
+            true => is_source_of_truth,
+            false => {
+                sender_role == Role::Player
+                    && key
+                        .split_once('_')
+                        .and_then(|(_, player_index)| player_index.parse::<u8>().ok())
+                        == Some(sender_index)
+            }
+        };
+        if !is_allowed {
+            return Err(FlashlightError::NotYourTurn);
+        }
+    }
+
+    let monster_cell = |glyphs: &[Glyph]| glyphs.iter().position(|glyph| *glyph == Glyph::Monster);
+    if monster_cell(previous) != monster_cell(next) {
+        let is_monster_controller = match monster_control(previous_doc) {
+            MonsterControl::Ai => is_source_of_truth,
+            MonsterControl::Spectator => sender_role == Role::Spectator,
+        };
+        if !is_monster_controller {
+            return Err(FlashlightError::NotYourTurn);
+        }
+    }
+
+    Ok(())
+}
+
+/// Who moves the monster according to `doc`, the AI unless someone took over.
+fn monster_control(doc: &Doc) -> MonsterControl {
+    let player_state = doc.get_or_insert_map("player_state");
+    let txn = doc.transact();
+
+    match player_state.get(&txn, MONSTER_CONTROL_KEY) {
+        Some(Out::Any(Any::String(monster_control))) => {
+            MonsterControl::from_str(&monster_control).unwrap_or(MonsterControl::Ai)
+        }
+        _ => MonsterControl::Ai,
+    }
+}
+
+/// Battery charge of the player at `player_index` according to `doc`, full until they've shared one.
+fn battery(doc: &Doc, player_index: u8) -> u8 {
+    let player_state = doc.get_or_insert_map("player_state");
+    let txn = doc.transact();
+
+    match player_state.get(&txn, &player_battery_key(player_index)) {
+        Some(Out::Any(charge)) => battery_charge(&charge).unwrap_or(MAX_BATTERY),
+        _ => MAX_BATTERY,
+    }
+}
+
+/// A battery charge as stored in the doc, a whole number up to `MAX_BATTERY`.
+fn battery_charge(value: &Any) -> Option<u8> {
+    match value {
+        Any::Number(charge)
+            if charge.fract() == 0. && (0. ..=MAX_BATTERY as f64).contains(charge) =>
+        {
+            Some(*charge as u8)
+        }
+        _ => None,
+    }
+}
+
+/// The shared player state as plain values, validated docs don't hold anything else.
+fn player_state_entries(doc: &Doc) -> HashMap<String, Any> {
+    let player_state = doc.get_or_insert_map("player_state");
+    let txn = doc.transact();
+
+    player_state
+        .iter(&txn)
+        .filter_map(|(key, value)| match value {
+            Out::Any(value) => Some((key.to_string(), value)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Checks that every player's cell is a `P` on the map, every facing is a direction,
+/// every battery holds a charge, every light is on or off and the monster is controlled by someone.
+///
+/// Compared with the `previous` doc, batteries may only drain by a turn's worth of charge.
+fn validate_player_state(
+    doc: &Doc,
+    glyphs: &[Glyph],
+    previous: Option<&Doc>,
+) -> Result<(), FlashlightError> {
+    let player_count = glyphs
+        .iter()
+        .filter(|glyph| **glyph == Glyph::Player)
+        .count();
+    let player_state = doc.get_or_insert_map("player_state");
+    let txn = doc.transact();
+    let mut player_cells = vec![];
+
+    for (key, value) in player_state.iter(&txn) {
+        if key == MONSTER_CONTROL_KEY {
+            match value {
+                Out::Any(Any::String(monster_control))
+                    if MonsterControl::from_str(&monster_control).is_some() => {}
+                _ => return Err(FlashlightError::DecodeFailed),
+            }
+            continue;
+        }
+
+        let Some((name, player_index)) = key.split_once('_') else {
+            return Err(FlashlightError::DecodeFailed);
+        };
+        match player_index.parse::<usize>() {
+            Ok(player_index) if player_index < player_count => {}
+            _ => return Err(FlashlightError::DecodeFailed),
+        }
+
+        match (name, value) {
+            ("cell", Out::Any(idx)) => {
+                let idx = u16::try_from(idx).map_err(|_| FlashlightError::DecodeFailed)?;
+                // two players can't share a cell either
+                if glyphs.get(idx as usize) != Some(&Glyph::Player) || player_cells.contains(&idx) {
+                    return Err(FlashlightError::IllegalMove);
+                }
+                player_cells.push(idx);
+            }
+            ("facing", Out::Any(Any::String(facing))) => {
+                let mut chars = facing.chars();
+                match (chars.next().and_then(Direction::from_char), chars.next()) {
+                    (Some(_), None) => {}
+                    _ => return Err(FlashlightError::DecodeFailed),
+                }
+            }
+            ("battery", Out::Any(charge)) if battery_charge(&charge).is_some() => {}
+            ("light", Out::Any(Any::Bool(_))) => {}
+            _ => return Err(FlashlightError::DecodeFailed),
+        }
+    }
+    drop(txn);
 
-        map_glyphs
+    let Some(previous) = previous else {
+        return Ok(());
+    };
+    for player_index in 0..player_count as u8 {
+        let drain = battery(previous, player_index).checked_sub(battery(doc, player_index));
+        match drain {
+            Some(drain) if drain <= BATTERY_DRAIN_PER_TURN => {}
+            _ => return Err(FlashlightError::IllegalMove),
+        }
     }
+
+    Ok(())
 }
 
 #[test]
@@ -596,7 +1590,7 @@ fn runs() {
     // . * * .
     let map: MapState = "_P*__.T_T...T**X.**.".into();
 
-    let flashlight = Flashlight::new(map.state.to_vec(), 4, 40, 4);
+    let flashlight = Flashlight::new(map.state.to_vec(), 4, 40, 4, Role::Player);
 
     assert_eq!(flashlight.width, 4);
 }
@@ -605,7 +1599,7 @@ fn runs() {
 fn has_correct_map_state() {
     let map: MapState = "_P*__.T_T...T**X.**.".into();
 
-    let flashlight = Flashlight::new(map.state.to_vec(), 4, 40, 4);
+    let flashlight = Flashlight::new(map.state.to_vec(), 4, 40, 4, Role::Player);
 
     let expected_map: MapState = "_P*__.T_T...T**X.**.".into();
     let expected_map_glyphs: Vec<Glyph> = expected_map.state;
@@ -624,16 +1618,16 @@ fn make_valid_move() {
     // . * * .
     let starting_map: MapState = "_P*__.T_T...T**X.**.".into();
 
-    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4);
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
 
-    let cells_to_select = vec![flashlight.idx_to_grid_position(5)];
+    let cells_to_select = [flashlight.idx_to_grid_position(5)];
 
-    let mut outcome = MoveOutcome::NoOp;
+    let mut outcome = Ok(MoveOutcome::NoOp);
     for cell in cells_to_select.iter() {
         outcome = flashlight.do_move_player(*cell);
     }
 
-    assert_eq!(outcome, MoveOutcome::Advance);
+    assert_eq!(outcome, Ok(MoveOutcome::Advance));
 
     let expected_map: MapState = "_.*__PT_T...T**X.**.".into();
 
@@ -643,7 +1637,7 @@ fn make_valid_move() {
 }
 
 #[test]
-fn reject_move_to_non_interactable_cell() {
+fn bump_into_non_interactable_cell() {
     // _ P * _
     // _ . T _
     // T . . .
@@ -651,15 +1645,18 @@ fn reject_move_to_non_interactable_cell() {
     // . * * .
     let map: MapState = "_P*__.T_T...T**X.**.".into();
 
-    let mut flashlight = Flashlight::new(map.state.to_vec(), 4, 40, 4);
+    let mut flashlight = Flashlight::new(map.state.to_vec(), 4, 40, 4, Role::Player);
 
     let water_cell = Vec2::new_with_data(0, 0);
     let outcome = flashlight.do_move_player(water_cell);
 
     let map_glyphs = flashlight.get_map_glyphs();
 
-    assert_eq!(outcome, MoveOutcome::Rejected);
+    assert_eq!(outcome, Ok(MoveOutcome::Bumped));
     assert_eq!(&map_glyphs[..], &map.state[..]);
+    // bumping still costs a turn
+    assert_eq!(flashlight.turn(), 1);
+    assert_eq!(flashlight.player_poise, 99);
 }
 
 #[cfg(test)]
@@ -676,7 +1673,15 @@ fn runs_using_js_data() {
             glyph as u8
         })
         .collect();
-    let flashlight = Flashlight::new_from_js(js_sys::Uint8Array::from(&map_state[..]), 4, 40, 4);
+    let flashlight = Flashlight::new_from_js(
+        js_sys::Uint8Array::from(&map_state[..]),
+        4,
+        40,
+        4,
+        Role::Player,
+        0,
+    )
+    .unwrap();
 
     assert_eq!(flashlight.width, 4);
 }
@@ -695,12 +1700,19 @@ fn make_valid_move_using_idx() {
         })
         .collect();
 
-    let mut flashlight =
-        Flashlight::new_from_js(js_sys::Uint8Array::from(&map_state[..]), 4, 40, 4);
+    let mut flashlight = Flashlight::new_from_js(
+        js_sys::Uint8Array::from(&map_state[..]),
+        4,
+        40,
+        4,
+        Role::Player,
+        0,
+    )
+    .unwrap();
 
     let outcome = flashlight.do_move_player(idx_to_grid_position(5, 4));
 
-    assert_eq!(outcome, MoveOutcome::Advance);
+    assert_eq!(outcome, Ok(MoveOutcome::Advance));
 
     // _ P * _
     // _ . T _
@@ -718,7 +1730,7 @@ fn make_valid_move_using_idx() {
 
     let outcome = flashlight.do_move_player(idx_to_grid_position(9, 4));
 
-    assert_eq!(outcome, MoveOutcome::Advance);
+    assert_eq!(outcome, Ok(MoveOutcome::Advance));
 
     let expected_map: MapState = "_.*__.T_TP..T**X.**.".into();
 
@@ -731,65 +1743,87 @@ fn make_valid_move_using_idx() {
 fn reject_oob_move() {
     let starting_map: MapState = "P...".into();
 
-    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 2, 40, 2);
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 2, 40, 2, Role::Player);
 
-    let cells_to_select = vec![Vec2::new_with_data(2, 2)];
+    let cells_to_select = [Vec2::new_with_data(2, 2)];
 
-    let mut outcome = MoveOutcome::NoOp;
+    let mut outcome = Ok(MoveOutcome::NoOp);
     for cell in cells_to_select.iter() {
         outcome = flashlight.do_move_player(*cell)
     }
 
-    assert_eq!(outcome, MoveOutcome::Rejected);
+    assert_eq!(outcome, Err(FlashlightError::OutOfBounds));
+}
+
+#[test]
+fn leave_game_untouched_after_refused_move() {
+    // _ P * _
+    // _ . T _
+    // T . . .
+    // T * * X
+    // . * * .
+    let starting_map: MapState = "_P*__.T_T...T**X.**.".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
+
+    // two cells down in one go
+    let outcome = flashlight.do_move_player(Vec2::new_with_data(1, 2));
+
+    assert_eq!(outcome, Err(FlashlightError::IllegalMove));
+    assert_eq!(flashlight.turn(), 0);
+    assert_eq!(flashlight.battery(), MAX_BATTERY);
+    assert_eq!(flashlight.player_poise, 100);
+    assert_eq!(flashlight.player_facing(), Direction::Down);
+    assert_eq!(&flashlight.get_map_glyphs()[..], &starting_map.state[..]);
 }
 
 #[test]
 fn move_monster() {
     let starting_map: MapState = "P..G............".into();
 
-    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4);
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
     flashlight.compute_visibility();
 
-    let cells_to_select = vec![Vec2::new_with_data(0, 1)];
+    let cells_to_select = [Vec2::new_with_data(0, 1)];
 
-    let mut outcome = MoveOutcome::NoOp;
+    let mut outcome = Ok(MoveOutcome::NoOp);
     for cell in cells_to_select.iter() {
         outcome = flashlight.do_move_player(*cell)
     }
 
-    assert_eq!(outcome, MoveOutcome::Advance);
+    assert_eq!(outcome, Ok(MoveOutcome::Advance));
 
     let outcome = flashlight.do_move_enemy();
 
-    assert_eq!(outcome, MoveOutcome::Advance);
+    assert_eq!(outcome, Ok(MoveOutcome::Advance));
 }
 
 #[test]
-fn reject_monster_move_that_bumps_into_player() {
+fn monster_bumps_into_player() {
     // P . . G
     // . . . .
     // . . . .
     // . . . .
     let starting_map: MapState = "P..G............".into();
 
-    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4);
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
     flashlight.compute_visibility();
 
-    let cells_to_select = vec![Vec2::new_with_data(0, 1)];
+    let cells_to_select = [Vec2::new_with_data(0, 1)];
 
-    let mut outcome = MoveOutcome::NoOp;
+    let mut outcome = Ok(MoveOutcome::NoOp);
     for cell in cells_to_select.iter() {
         outcome = flashlight.do_move_player(*cell)
     }
 
-    assert_eq!(outcome, MoveOutcome::Advance);
+    assert_eq!(outcome, Ok(MoveOutcome::Advance));
 
-    let mut outcome = MoveOutcome::NoOp;
+    let mut outcome = Ok(MoveOutcome::NoOp);
     for _ in 0..4 {
         outcome = flashlight.do_move_enemy();
     }
 
-    assert_eq!(outcome, MoveOutcome::Rejected);
+    assert_eq!(outcome, Ok(MoveOutcome::Bumped));
     assert_eq!(flashlight.monster_cell, Vec2(0, 0));
 }
 
@@ -806,16 +1840,16 @@ fn show_correct_visibility() {
     // 1 P 1 1
     // 1 1 1 1
     // 0 0 0 0
-    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4);
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
 
     flashlight.compute_visibility();
     let map_length = (flashlight.height * flashlight.width) as usize;
     let mut visibility_state = vec![-1; map_length];
 
-    for idx in 0..map_length {
+    for (idx, visibility) in visibility_state.iter_mut().enumerate() {
         let Vec2(x, y) = idx_to_grid_position(idx as u16, flashlight.width);
         let pos = IVec2 { x, y };
-        visibility_state[idx] = match flashlight.visibility_state.contains_key(&pos) {
+        *visibility = match flashlight.visibility_state.contains_key(&pos) {
             true => 1,
             false => 0,
         };
@@ -840,7 +1874,7 @@ fn show_correct_visibility_from_js() {
     // 1 P 1 1
     // 1 1 1 1
     // 0 0 0 0
-    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4);
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
 
     flashlight.compute_visibility();
     let visibility = flashlight.visibility_state();
@@ -857,9 +1891,9 @@ fn pick_up_health_pack() {
     // . * * .
     let starting_map: MapState = "_P*__.T_T...T**X.**.".into();
 
-    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4);
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
 
-    let cells_to_select = vec![
+    let cells_to_select = [
         flashlight.idx_to_grid_position(5),
         flashlight.idx_to_grid_position(9),
         flashlight.idx_to_grid_position(10),
@@ -868,15 +1902,113 @@ fn pick_up_health_pack() {
     ];
     let player_poise = flashlight.player_poise;
 
-    let mut outcome = MoveOutcome::NoOp;
+    let mut outcome = Ok(MoveOutcome::NoOp);
     for cell in cells_to_select.iter() {
         outcome = flashlight.do_move_player(*cell)
     }
 
-    assert_eq!(outcome, MoveOutcome::Advance);
+    assert_eq!(outcome, Ok(MoveOutcome::Advance));
     assert!(flashlight.player_poise > player_poise);
 }
 
+#[test]
+fn drain_battery_while_flashlight_is_on() {
+    // _ P * _
+    // _ . T _
+    // T . . .
+    // T * * X
+    // . * * .
+    let starting_map: MapState = "_P*__.T_T...T**X.**.".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
+
+    let outcome = flashlight.do_move_player(flashlight.idx_to_grid_position(5));
+
+    assert_eq!(outcome, Ok(MoveOutcome::Advance));
+    assert_eq!(flashlight.battery(), MAX_BATTERY - BATTERY_DRAIN_PER_TURN);
+
+    assert!(!flashlight.toggle_flashlight());
+
+    let outcome = flashlight.do_move_player(flashlight.idx_to_grid_position(9));
+
+    assert_eq!(outcome, Ok(MoveOutcome::Advance));
+    assert_eq!(flashlight.battery(), MAX_BATTERY - BATTERY_DRAIN_PER_TURN);
+}
+
+#[test]
+fn shrink_visibility_as_battery_runs_low() {
+    let starting_map: MapState =
+        "...........................P....................................".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 8, 40, 8, Role::Player);
+
+    flashlight.compute_visibility();
+    let fully_charged = flashlight.visibility_state.len();
+
+    flashlight.set_player_state(player_battery_key(0), u16::from(MAX_BATTERY / 4).into());
+    flashlight.compute_visibility();
+    let low_battery = flashlight.visibility_state.len();
+
+    flashlight.set_player_state(player_battery_key(0), 0u16.into());
+    flashlight.compute_visibility();
+    let empty_battery = flashlight.visibility_state.len();
+
+    assert!(fully_charged > low_battery);
+    assert!(low_battery > empty_battery);
+    assert_eq!(flashlight.flashlight_radius(0), AMBIENT_LIGHT_RADIUS);
+}
+
+#[test]
+fn show_only_ambient_light_when_flashlight_is_off() {
+    // . . . .
+    // . P . .
+    // . . . .
+    // . . . .
+    let starting_map: MapState = ".....P..........".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
+
+    flashlight.toggle_flashlight();
+    flashlight.compute_visibility();
+
+    let player = IVec2 { x: 1, y: 1 };
+    assert!(
+        flashlight
+            .visibility_state
+            .keys()
+            .all(|pos| player.distance_squared(*pos) < AMBIENT_LIGHT_RADIUS.pow(2))
+    );
+    assert!(
+        flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 1, y: 0 })
+    );
+}
+
+#[test]
+fn spare_monster_when_flashlight_is_off() {
+    // P G . .
+    // . . . .
+    // . . . .
+    // . . . .
+    let starting_map: MapState = "PG..............".into();
+
+    let mut lit = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
+    lit.compute_visibility();
+    let outcome = lit.do_move_player(Vec2::new_with_data(1, 0));
+
+    assert_eq!(outcome, Ok(MoveOutcome::Bumped));
+    assert!(lit.monster_poise < 120);
+
+    let mut dark = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
+    dark.toggle_flashlight();
+    dark.compute_visibility();
+    let outcome = dark.do_move_player(Vec2::new_with_data(1, 0));
+
+    assert_eq!(outcome, Ok(MoveOutcome::Bumped));
+    assert_eq!(dark.monster_poise, 120);
+}
+
 #[test]
 fn test_flashlight_state_vector_sync() {
     use std::sync::mpsc;
@@ -886,7 +2018,7 @@ fn test_flashlight_state_vector_sync() {
     let starting_map_2: MapState = "_...................".into();
 
     // Initialize first flashlight instance with the map
-    let mut flashlight_a = Flashlight::new(starting_map.state.clone(), 4, 40, 4);
+    let mut flashlight_a = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Player);
 
     // Get the initial state vector from the first instance
     let initial_state_vector = {
@@ -895,10 +2027,12 @@ fn test_flashlight_state_vector_sync() {
     };
 
     // Initialize second flashlight instance (empty initially)
-    let mut flashlight_b = Flashlight::new(starting_map_2.state.clone(), 4, 40, 4);
+    let mut flashlight_b = Flashlight::new(starting_map_2.state.clone(), 4, 40, 4, Role::Spectator);
 
     // Apply the initial state vector to the second instance
-    flashlight_b.apply_initial_state_vector(&initial_state_vector);
+    flashlight_b
+        .apply_initial_state_vector(&initial_state_vector)
+        .unwrap();
 
     // Verify both instances have the same initial state
     let state_a_initial = flashlight_a.get_map_glyphs();
@@ -919,16 +2053,16 @@ fn test_flashlight_state_vector_sync() {
     // Make a move on the first flashlight (move player from position 1 to position 5)
     let move_result = flashlight_a.do_move_player(flashlight_a.idx_to_grid_position(5));
 
-    assert_eq!(move_result, MoveOutcome::Advance);
+    assert_eq!(move_result, Ok(MoveOutcome::Advance));
 
-    // Receive and apply both deltas that arrive from the move
-    // First delta
-    let delta1 = rx.recv().unwrap();
-    flashlight_b.apply_delta(&delta1);
-
-    // Second delta
-    let delta2 = rx.recv().unwrap();
-    flashlight_b.apply_delta(&delta2);
+    // The move and the player's new cell arrive in one delta
+    let delta = rx.recv().unwrap();
+    flashlight_b.apply_delta(&delta, Role::Player, 0).unwrap();
+    // followed by the drained battery
+    let delta = rx.recv().unwrap();
+    flashlight_b.apply_delta(&delta, Role::Player, 0).unwrap();
+    assert!(rx.try_recv().is_err());
+    assert_eq!(flashlight_b.battery_of(0), flashlight_a.battery());
 
     // Get the final states
     let state_a_final = flashlight_a.get_map_glyphs();
@@ -937,3 +2071,1072 @@ fn test_flashlight_state_vector_sync() {
     // Compare final states - they should be identical
     assert_eq!(state_a_final, state_b_final);
 }
+
+#[test]
+fn face_direction_of_last_move() {
+    // _ P * _
+    // _ . T _
+    // T . . .
+    // T * * X
+    // . * * .
+    let starting_map: MapState = "_P*__.T_T...T**X.**.".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
+
+    assert_eq!(flashlight.player_facing(), Direction::Down);
+
+    // bumping into water still turns the player around
+    let outcome = flashlight.do_move_player(Vec2::new_with_data(0, 0));
+
+    assert_eq!(outcome, Ok(MoveOutcome::Bumped));
+    assert_eq!(flashlight.player_facing(), Direction::Left);
+
+    let cells_to_select = [
+        flashlight.idx_to_grid_position(5),
+        flashlight.idx_to_grid_position(9),
+        flashlight.idx_to_grid_position(10),
+    ];
+    for cell in cells_to_select.iter() {
+        flashlight.do_move_player(*cell).unwrap();
+    }
+
+    assert_eq!(flashlight.player_facing(), Direction::Right);
+}
+
+#[test]
+fn limit_visibility_to_flashlight_cone() {
+    let starting_map: MapState =
+        "...........................P....................................".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 8, 40, 8, Role::Player);
+    flashlight.visibility_mode = VisibilityMode::Cone;
+
+    // player is facing down
+    flashlight.compute_visibility();
+
+    // in the beam
+    assert!(
+        flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 3, y: 7 })
+    );
+    assert!(
+        flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 5, y: 6 })
+    );
+    // ambient light
+    assert!(
+        flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 3, y: 2 })
+    );
+    assert!(
+        flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 2, y: 3 })
+    );
+    // behind and beside the player
+    assert!(
+        !flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 3, y: 0 })
+    );
+    assert!(
+        !flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 0, y: 3 })
+    );
+
+    flashlight.visibility_mode = VisibilityMode::Omnidirectional;
+    flashlight.compute_visibility();
+
+    assert!(
+        flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 3, y: 0 })
+    );
+}
+
+#[test]
+fn sync_player_facing() {
+    use std::sync::mpsc;
+
+    // . P . .
+    // . . . .
+    let starting_map: MapState = ".P......".into();
+
+    let mut flashlight_a = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Player);
+    let mut flashlight_b = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Spectator);
+
+    let initial_state_vector = {
+        let txn = flashlight_a.map_state_doc.transact();
+        txn.encode_state_as_update_v1(&yrs::StateVector::default())
+    };
+    flashlight_b
+        .apply_initial_state_vector(&initial_state_vector)
+        .unwrap();
+
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    let _subscription = flashlight_a
+        .map_state_doc
+        .observe_update_v1(move |_txn, event| {
+            tx.send(event.update.clone()).unwrap();
+        })
+        .unwrap();
+
+    let outcome = flashlight_a.do_move_player(Vec2::new_with_data(2, 0));
+
+    assert_eq!(outcome, Ok(MoveOutcome::Advance));
+
+    for delta in rx.try_iter() {
+        flashlight_b.apply_delta(&delta, Role::Player, 0).unwrap();
+    }
+
+    assert_eq!(flashlight_a.player_facing(), Direction::Right);
+    assert_eq!(flashlight_b.player_facing(), Direction::Right);
+}
+
+#[test]
+fn remember_explored_tiles() {
+    // . . . . . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    // . . . P . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    // . . . G . . . .
+    // . . . . . . . .
+    let starting_map: MapState =
+        "...........................P.......................G............".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 8, 40, 8, Role::Player);
+    flashlight.visibility_mode = VisibilityMode::Cone;
+
+    // facing down towards the monster
+    flashlight.compute_visibility();
+
+    let monster_idx = 51;
+    assert_eq!(
+        flashlight.get_clipped_tile_visibility()[monster_idx],
+        TileVisibility::Visible
+    );
+    assert_eq!(
+        flashlight.get_clipped_map_state()[monster_idx],
+        Glyph::Monster
+    );
+
+    // turn around
+    let outcome = flashlight.do_move_player(Vec2::new_with_data(3, 2));
+    assert_eq!(outcome, Ok(MoveOutcome::Advance));
+    flashlight.compute_visibility();
+
+    let tile_visibility = flashlight.get_clipped_tile_visibility();
+    let map_state = flashlight.get_clipped_map_state();
+
+    assert_eq!(tile_visibility[monster_idx], TileVisibility::Remembered);
+    assert_eq!(map_state[monster_idx], Glyph::Floor);
+    assert_eq!(tile_visibility[3], TileVisibility::Visible);
+    assert_eq!(tile_visibility[0], TileVisibility::Unknown);
+}
+
+#[test]
+fn reject_player_moves_from_spectator() {
+    // _ P * _
+    // _ . T _
+    // T . . .
+    // T * * X
+    // . * * .
+    let starting_map: MapState = "_P*__.T_T...T**X.**.".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Spectator);
+
+    let outcome = flashlight.do_move_player(flashlight.idx_to_grid_position(5));
+
+    assert_eq!(outcome, Err(FlashlightError::NotYourTurn));
+    assert_eq!(
+        flashlight.do_move_enemy(),
+        Err(FlashlightError::NotYourTurn)
+    );
+    assert!(flashlight.toggle_flashlight());
+    assert_eq!(&flashlight.get_map_glyphs()[..], &starting_map.state[..]);
+}
+
+#[test]
+fn show_full_map_to_spectator() {
+    // _ . * _
+    // _ * T _
+    // T P . .
+    // T * * X
+    let starting_map: MapState = "_.*__*T_TP..T**X".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Spectator);
+
+    flashlight.compute_visibility();
+
+    assert_eq!(flashlight.visibility_state.len(), 16);
+    assert!(
+        flashlight
+            .get_clipped_tile_visibility()
+            .iter()
+            .all(|tile| matches!(tile, TileVisibility::Visible | TileVisibility::Unlit))
+    );
+    assert_eq!(
+        &flashlight.get_clipped_map_state()[..],
+        &starting_map.state[..]
+    );
+}
+
+#[test]
+fn light_the_players_cone_on_the_spectators_full_map() {
+    // . . . G . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    // . . . P . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    let starting_map: MapState =
+        "...G.......................P....................................".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 8, 40, 8, Role::Spectator);
+    flashlight.visibility_mode = VisibilityMode::Cone;
+    flashlight.compute_visibility();
+
+    // facing down, away from the monster
+    let tile_visibility = flashlight.get_clipped_tile_visibility();
+    assert_eq!(tile_visibility[43], TileVisibility::Visible);
+    assert_eq!(tile_visibility[3], TileVisibility::Unlit);
+    assert_eq!(flashlight.get_clipped_map_state()[3], Glyph::Monster);
+
+    // the player turns around on their own engine
+    let mut player = Flashlight::new(starting_map.state.to_vec(), 8, 40, 8, Role::Player);
+    let initial_state_vector = {
+        let txn = player.map_state_doc.transact();
+        txn.encode_state_as_update_v1(&yrs::StateVector::default())
+    };
+    flashlight
+        .apply_initial_state_vector(&initial_state_vector)
+        .unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel::<Vec<u8>>();
+    let _subscription = player
+        .map_state_doc
+        .observe_update_v1(move |_txn, event| {
+            tx.send(event.update.clone()).unwrap();
+        })
+        .unwrap();
+    player.do_move_player(Vec2::new_with_data(3, 2)).unwrap();
+    for delta in rx.try_iter() {
+        flashlight.apply_delta(&delta, Role::Player, 0).unwrap();
+    }
+    flashlight.compute_visibility();
+
+    let tile_visibility = flashlight.get_clipped_tile_visibility();
+    assert_eq!(tile_visibility[3], TileVisibility::Visible);
+    assert_eq!(tile_visibility[51], TileVisibility::Unlit);
+}
+
+#[test]
+fn show_monster_view_to_spectator() {
+    // G . T . . . . .
+    // . . T . . . . .
+    // T T T . . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    // . . . . . . . P
+    // . . . . . . . .
+    let starting_map: MapState =
+        "G.T.......T.....TTT....................................P........".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 8, 40, 8, Role::Spectator);
+    flashlight.spectator_view = SpectatorView::Monster;
+
+    flashlight.compute_visibility();
+
+    assert!(
+        flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 1, y: 1 })
+    );
+    // walled in by trees
+    assert!(
+        !flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 4, y: 4 })
+    );
+    assert!(
+        !flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 7, y: 6 })
+    );
+}
+
+#[test]
+fn move_monster_as_spectator() {
+    use std::sync::mpsc;
+
+    // P . . .
+    // . . . .
+    // . . T .
+    // . . . G
+    let starting_map: MapState = "P.........T....G".into();
+
+    take_sent_state_vectors();
+    let mut player = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
+    let mut spectator = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Spectator);
+    for state_vector in take_sent_state_vectors() {
+        spectator.apply_initial_state_vector(&state_vector).unwrap();
+    }
+
+    // AI runs the monster by default
+    assert_eq!(
+        spectator.do_move_monster(Vec2::new_with_data(3, 2)),
+        Err(FlashlightError::NotYourTurn)
+    );
+
+    // the player decides who runs the monster, the spectator follows along
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    let _subscription = player
+        .map_state_doc
+        .observe_update_v1(move |_txn, event| {
+            tx.send(event.update.clone()).unwrap();
+        })
+        .unwrap();
+    assert_eq!(
+        spectator.set_monster_control(MonsterControl::Spectator),
+        Err(FlashlightError::NotYourTurn)
+    );
+    player
+        .set_monster_control(MonsterControl::Spectator)
+        .unwrap();
+    for delta in rx.try_iter() {
+        spectator.apply_delta(&delta, Role::Player, 0).unwrap();
+    }
+    assert_eq!(spectator.monster_control(), MonsterControl::Spectator);
+
+    assert_eq!(player.do_move_enemy(), Ok(MoveOutcome::NoOp));
+    assert_eq!(
+        player.do_move_monster(Vec2::new_with_data(3, 2)),
+        Err(FlashlightError::NotYourTurn)
+    );
+
+    // same checks as player moves
+    assert_eq!(
+        spectator.do_move_monster(Vec2::new_with_data(4, 3)),
+        Err(FlashlightError::OutOfBounds)
+    );
+    assert_eq!(
+        spectator.do_move_monster(Vec2::new_with_data(1, 1)),
+        Err(FlashlightError::IllegalMove)
+    );
+    assert_eq!(
+        spectator.do_move_monster(Vec2::new_with_data(3, 2)),
+        Ok(MoveOutcome::Advance)
+    );
+
+    let expected_map: MapState = "P.........TG....".into();
+    assert_eq!(&spectator.get_map_glyphs()[..], &expected_map.state[..]);
+}
+
+#[test]
+fn show_monster_view_to_monster_player() {
+    // G . T . . . . .
+    // . . T . . . . .
+    // T T T . . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    // . . . . . . . P
+    // . . . . . . . .
+    let starting_map: MapState =
+        "G.T.......T.....TTT....................................P........".into();
+
+    let mut player = Flashlight::new(starting_map.state.to_vec(), 8, 40, 8, Role::Player);
+    player
+        .set_monster_control(MonsterControl::Spectator)
+        .unwrap();
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 8, 40, 8, Role::Spectator);
+    flashlight
+        .apply_initial_state_vector(&player.encode_doc())
+        .unwrap();
+
+    flashlight.compute_visibility();
+
+    assert!(
+        !flashlight
+            .visibility_state
+            .contains_key(&IVec2 { x: 7, y: 6 })
+    );
+}
+
+#[test]
+fn hand_monster_back_to_ai() {
+    let starting_map: MapState = "P..G............".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
+    flashlight
+        .set_monster_control(MonsterControl::Spectator)
+        .unwrap();
+    flashlight.compute_visibility();
+
+    assert_eq!(flashlight.do_move_enemy(), Ok(MoveOutcome::NoOp));
+
+    // monster player disconnected
+    flashlight.set_monster_control(MonsterControl::Ai).unwrap();
+
+    assert_eq!(flashlight.do_move_enemy(), Ok(MoveOutcome::Advance));
+}
+
+#[test]
+fn keep_track_of_multiple_players() {
+    use std::sync::mpsc;
+
+    // P . . P
+    // . . . .
+    let starting_map: MapState = "P..P....".into();
+
+    take_sent_state_vectors();
+    let mut flashlight_a =
+        Flashlight::new_with_player_index(starting_map.state.clone(), 4, 40, 4, Role::Player, 0);
+    let mut flashlight_b =
+        Flashlight::new_with_player_index(starting_map.state.clone(), 4, 40, 4, Role::Player, 1);
+
+    // only the first player shares their map
+    let sent = take_sent_state_vectors();
+    assert_eq!(sent.len(), 1);
+    for state_vector in sent.iter() {
+        flashlight_a
+            .apply_initial_state_vector(state_vector)
+            .unwrap();
+        flashlight_b
+            .apply_initial_state_vector(state_vector)
+            .unwrap();
+    }
+
+    let (tx_a, rx_a) = mpsc::channel::<Vec<u8>>();
+    let _subscription_a = flashlight_a
+        .map_state_doc
+        .observe_update_v1(move |_txn, event| {
+            tx_a.send(event.update.clone()).unwrap();
+        })
+        .unwrap();
+    let (tx_b, rx_b) = mpsc::channel::<Vec<u8>>();
+    let _subscription_b = flashlight_b
+        .map_state_doc
+        .observe_update_v1(move |_txn, event| {
+            tx_b.send(event.update.clone()).unwrap();
+        })
+        .unwrap();
+
+    assert_eq!(flashlight_a.player_count(), 2);
+    assert_eq!(flashlight_b.map_metadata().player_cell_idx, 3);
+
+    // . . . .
+    // P . . .
+    assert_eq!(
+        flashlight_a.do_move_player(Vec2::new_with_data(0, 1)),
+        Ok(MoveOutcome::Advance)
+    );
+    for delta in rx_a.try_iter() {
+        flashlight_b.apply_delta(&delta, Role::Player, 0).unwrap();
+    }
+
+    // b walks over to where a started, which puts b first in map order
+    for cell in [Vec2(2, 0), Vec2(1, 0), Vec2(0, 0)] {
+        assert_eq!(flashlight_b.do_move_player(cell), Ok(MoveOutcome::Advance));
+    }
+    let deltas: Vec<Vec<u8>> = rx_b.try_iter().collect();
+    for delta in deltas.iter() {
+        flashlight_a.apply_delta(delta, Role::Player, 1).unwrap();
+    }
+
+    assert_eq!(flashlight_a.get_map_glyphs(), flashlight_b.get_map_glyphs());
+    assert_eq!(flashlight_a.map_metadata().player_cell_idx, 4);
+    assert_eq!(flashlight_b.map_metadata().player_cell_idx, 0);
+}
+
+#[test]
+fn run_monster_on_first_player_only() {
+    // P . . G
+    // . . . .
+    // . . . .
+    // . . . P
+    let starting_map: MapState = "P..G...........P".into();
+
+    let mut flashlight_a =
+        Flashlight::new_with_player_index(starting_map.state.clone(), 4, 40, 4, Role::Player, 0);
+    let mut flashlight_b =
+        Flashlight::new_with_player_index(starting_map.state.clone(), 4, 40, 4, Role::Player, 1);
+    flashlight_a.compute_visibility();
+    flashlight_b.compute_visibility();
+
+    assert_eq!(
+        flashlight_b.do_move_enemy(),
+        Err(FlashlightError::NotYourTurn)
+    );
+    assert_eq!(flashlight_a.do_move_enemy(), Ok(MoveOutcome::Advance));
+}
+
+#[test]
+fn promote_spectator_to_player() {
+    // P . . P
+    // . . . .
+    let starting_map: MapState = "P..P....".into();
+
+    let mut player =
+        Flashlight::new_with_player_index(starting_map.state.clone(), 4, 40, 4, Role::Player, 1);
+    let mut spectator = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Spectator);
+    take_sent_state_vectors();
+
+    // the first player left and the spectator got their slot
+    spectator.set_role(Role::Player, 0);
+
+    let sent = take_sent_state_vectors();
+    assert_eq!(sent.len(), 1);
+    player.apply_initial_state_vector(&sent[0]).unwrap();
+
+    assert_eq!(spectator.map_metadata().player_cell_idx, 0);
+    assert_eq!(
+        spectator.do_move_player(Vec2::new_with_data(0, 1)),
+        Ok(MoveOutcome::Advance)
+    );
+    assert_eq!(spectator.do_move_enemy(), Ok(MoveOutcome::NoOp));
+
+    spectator.set_role(Role::Spectator, 0);
+    assert_eq!(
+        spectator.do_move_player(Vec2::new_with_data(0, 0)),
+        Err(FlashlightError::NotYourTurn)
+    );
+}
+
+#[test]
+fn reject_deltas_that_teleport_the_player() {
+    // . P . .
+    // . . . X
+    let starting_map: MapState = ".P.....X".into();
+
+    let flashlight_a = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Player);
+    let mut flashlight_b = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Spectator);
+
+    let initial_state_vector = {
+        let txn = flashlight_a.map_state_doc.transact();
+        txn.encode_state_as_update_v1(&yrs::StateVector::default())
+    };
+    flashlight_b
+        .apply_initial_state_vector(&initial_state_vector)
+        .unwrap();
+
+    // a tampered client writes the player straight onto the target
+    let forged_delta = {
+        let map_state = flashlight_a.map_state_doc.get_or_insert_text("map_state");
+        let mut txn = flashlight_a.map_state_doc.transact_mut();
+        map_state.remove_range(&mut txn, 1, 1);
+        map_state.insert(&mut txn, 1, ".");
+        map_state.remove_range(&mut txn, 7, 1);
+        map_state.insert(&mut txn, 7, "P");
+        txn.encode_update_v1()
+    };
+    assert_eq!(
+        flashlight_b.apply_delta(&forged_delta, Role::Player, 0),
+        Err(FlashlightError::IllegalMove)
+    );
+
+    assert_eq!(flashlight_b.get_map_glyphs(), starting_map.state);
+    assert_eq!(flashlight_b.map_metadata().player_cell_idx, 1);
+}
+
+#[test]
+fn reject_deltas_with_unknown_glyphs() {
+    let starting_map: MapState = ".P.....X".into();
+
+    let flashlight_a = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Player);
+    let mut flashlight_b = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Spectator);
+
+    let initial_state_vector = {
+        let txn = flashlight_a.map_state_doc.transact();
+        txn.encode_state_as_update_v1(&yrs::StateVector::default())
+    };
+    flashlight_b
+        .apply_initial_state_vector(&initial_state_vector)
+        .unwrap();
+
+    let forged_delta = {
+        let map_state = flashlight_a.map_state_doc.get_or_insert_text("map_state");
+        let mut txn = flashlight_a.map_state_doc.transact_mut();
+        map_state.remove_range(&mut txn, 0, 1);
+        map_state.insert(&mut txn, 0, "Z");
+        txn.encode_update_v1()
+    };
+
+    assert_eq!(
+        flashlight_b.apply_delta(&forged_delta, Role::Player, 0),
+        Err(FlashlightError::InvalidGlyph)
+    );
+    assert_eq!(
+        flashlight_b.apply_delta(&[1, 2, 3], Role::Player, 0),
+        Err(FlashlightError::DecodeFailed)
+    );
+    assert_eq!(flashlight_b.get_map_glyphs(), starting_map.state);
+}
+
+#[test]
+fn reject_deltas_that_misplace_players() {
+    // . P . .
+    // . . . X
+    let starting_map: MapState = ".P.....X".into();
+
+    let flashlight_a = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Player);
+    let mut flashlight_b = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Spectator);
+
+    flashlight_b
+        .apply_initial_state_vector(&flashlight_a.encode_doc())
+        .unwrap();
+
+    // every forged delta starts from a's doc, so none depends on an earlier refused one
+    let forge = |key: &str, value: Any| {
+        let doc = Doc::new();
+        let player_state = doc.get_or_insert_map("player_state");
+        let mut txn = doc.transact_mut();
+        txn.apply_update(Update::decode_v1(&flashlight_a.encode_doc()).unwrap())
+            .unwrap();
+        let before = txn.state_vector();
+        player_state.insert(&mut txn, key, value);
+        txn.encode_state_as_update_v1(&before)
+    };
+
+    // the map says the player is still at 1
+    assert_eq!(
+        flashlight_b.apply_delta(&forge("cell_0", Any::from(5)), Role::Player, 0),
+        Err(FlashlightError::IllegalMove)
+    );
+    assert_eq!(
+        flashlight_b.apply_delta(&forge("facing_0", Any::from("Z")), Role::Player, 0),
+        Err(FlashlightError::DecodeFailed)
+    );
+    assert_eq!(
+        flashlight_b.apply_delta(&forge("cell_1", Any::from(1)), Role::Player, 0),
+        Err(FlashlightError::DecodeFailed)
+    );
+    assert_eq!(
+        flashlight_b.apply_delta(
+            &forge("monster_control", Any::from("Nobody")),
+            Role::Player,
+            0
+        ),
+        Err(FlashlightError::DecodeFailed)
+    );
+
+    assert_eq!(flashlight_b.map_metadata().player_cell_idx, 1);
+    assert_eq!(flashlight_b.player_facing(), Direction::Down);
+}
+
+#[test]
+fn reject_deltas_that_recharge_or_overdrain_the_battery() {
+    // . P . .
+    // . . . X
+    let starting_map: MapState = ".P.....X".into();
+
+    let mut flashlight_a = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Player);
+    let mut flashlight_b = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Spectator);
+
+    flashlight_b
+        .apply_initial_state_vector(&flashlight_a.encode_doc())
+        .unwrap();
+
+    let forge = |flashlight: &Flashlight, key: &str, value: Any| {
+        let doc = Doc::new();
+        let player_state = doc.get_or_insert_map("player_state");
+        let mut txn = doc.transact_mut();
+        txn.apply_update(Update::decode_v1(&flashlight.encode_doc()).unwrap())
+            .unwrap();
+        let before = txn.state_vector();
+        player_state.insert(&mut txn, key, value);
+        txn.encode_state_as_update_v1(&before)
+    };
+
+    let overdrained = u16::from(MAX_BATTERY - 2 * BATTERY_DRAIN_PER_TURN);
+    for (key, value, error) in [
+        (
+            "battery_0",
+            Any::from(overdrained),
+            FlashlightError::IllegalMove,
+        ),
+        ("battery_0", Any::from(0.5), FlashlightError::DecodeFailed),
+        (
+            "battery_0",
+            Any::from(u16::from(MAX_BATTERY) + 1),
+            FlashlightError::DecodeFailed,
+        ),
+        ("light_0", Any::from("off"), FlashlightError::DecodeFailed),
+    ] {
+        assert_eq!(
+            flashlight_b.apply_delta(&forge(&flashlight_a, key, value), Role::Player, 0),
+            Err(error)
+        );
+    }
+    assert_eq!(flashlight_b.battery(), MAX_BATTERY);
+
+    // a turn's worth goes through, and can't be taken back
+    let drained = u16::from(MAX_BATTERY - BATTERY_DRAIN_PER_TURN);
+    let delta = forge(&flashlight_a, "battery_0", Any::from(drained));
+    flashlight_b.apply_delta(&delta, Role::Player, 0).unwrap();
+    flashlight_a.apply_delta(&delta, Role::Player, 0).unwrap();
+    assert_eq!(
+        flashlight_b.apply_delta(
+            &forge(
+                &flashlight_a,
+                "battery_0",
+                Any::from(u16::from(MAX_BATTERY))
+            ),
+            Role::Player,
+            0
+        ),
+        Err(FlashlightError::IllegalMove)
+    );
+    assert_eq!(flashlight_b.battery(), MAX_BATTERY - BATTERY_DRAIN_PER_TURN);
+}
+
+#[test]
+fn chase_players_by_their_shared_light() {
+    use std::sync::mpsc;
+
+    // . . . . . . . P
+    // . . . . . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    // G . . . . . . .
+    // . . . . . . . .
+    // . . . . . . . .
+    // P . . . . . . .
+    let starting_map: MapState =
+        ".......P........................G.......................P.......".into();
+
+    take_sent_state_vectors();
+    let mut flashlight_a =
+        Flashlight::new_with_player_index(starting_map.state.clone(), 8, 40, 8, Role::Player, 0);
+    let mut flashlight_b =
+        Flashlight::new_with_player_index(starting_map.state.clone(), 8, 40, 8, Role::Player, 1);
+    for state_vector in take_sent_state_vectors() {
+        flashlight_b
+            .apply_initial_state_vector(&state_vector)
+            .unwrap();
+    }
+
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    let _subscription = flashlight_b
+        .map_state_doc
+        .observe_update_v1(move |_txn, event| {
+            tx.send(event.update.clone()).unwrap();
+        })
+        .unwrap();
+
+    // with both flashlights off the monster can't see anyone
+    assert!(!flashlight_a.toggle_flashlight());
+    assert!(!flashlight_b.toggle_flashlight());
+    for delta in rx.try_iter() {
+        flashlight_a.apply_delta(&delta, Role::Player, 1).unwrap();
+    }
+    assert!(!flashlight_a.is_light_on_of(1));
+    assert_eq!(flashlight_a.do_move_enemy(), Ok(MoveOutcome::NoOp));
+
+    // b's light reaches the monster on a's engine too
+    assert!(flashlight_b.toggle_flashlight());
+    for delta in rx.try_iter() {
+        flashlight_a.apply_delta(&delta, Role::Player, 1).unwrap();
+    }
+    assert_eq!(flashlight_a.do_move_enemy(), Ok(MoveOutcome::Advance));
+}
+
+#[test]
+fn reject_deltas_from_peers_that_dont_control_the_change() {
+    use std::sync::mpsc;
+
+    // P . . G
+    // . . . .
+    // . . . .
+    // . . . P
+    let starting_map: MapState = "P..G...........P".into();
+
+    take_sent_state_vectors();
+    let mut flashlight_a =
+        Flashlight::new_with_player_index(starting_map.state.clone(), 4, 40, 4, Role::Player, 0);
+    let mut flashlight_b =
+        Flashlight::new_with_player_index(starting_map.state.clone(), 4, 40, 4, Role::Player, 1);
+    let mut spectator = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Spectator);
+    for state_vector in take_sent_state_vectors() {
+        flashlight_b
+            .apply_initial_state_vector(&state_vector)
+            .unwrap();
+        spectator.apply_initial_state_vector(&state_vector).unwrap();
+    }
+
+    let capture = |flashlight: &Flashlight| {
+        let (tx, rx) = mpsc::channel::<Vec<u8>>();
+        let subscription = flashlight
+            .map_state_doc
+            .observe_update_v1(move |_txn, event| {
+                tx.send(event.update.clone()).unwrap();
+            })
+            .unwrap();
+        (subscription, rx)
+    };
+
+    // b's step only comes from b
+    let (subscription, rx) = capture(&flashlight_b);
+    assert_eq!(
+        flashlight_b.do_move_player(Vec2(3, 2)),
+        Ok(MoveOutcome::Advance)
+    );
+    drop(subscription);
+    let b_step: Vec<Vec<u8>> = rx.try_iter().collect();
+    for delta in b_step.iter() {
+        assert_eq!(
+            spectator.apply_delta(delta, Role::Player, 0),
+            Err(FlashlightError::NotYourTurn)
+        );
+        assert_eq!(
+            spectator.apply_delta(delta, Role::Spectator, 0),
+            Err(FlashlightError::NotYourTurn)
+        );
+        spectator.apply_delta(delta, Role::Player, 1).unwrap();
+    }
+
+    // the AI monster only runs on a
+    let (subscription, rx) = capture(&flashlight_a);
+    flashlight_a.compute_visibility();
+    assert_eq!(flashlight_a.do_move_enemy(), Ok(MoveOutcome::Advance));
+    drop(subscription);
+    let monster_step: Vec<Vec<u8>> = rx.try_iter().collect();
+    for delta in monster_step.iter() {
+        assert_eq!(
+            spectator.apply_delta(delta, Role::Player, 1),
+            Err(FlashlightError::NotYourTurn)
+        );
+        assert_eq!(
+            spectator.apply_delta(delta, Role::Spectator, 0),
+            Err(FlashlightError::NotYourTurn)
+        );
+        spectator.apply_delta(delta, Role::Player, 0).unwrap();
+    }
+
+    // only a hands the monster over
+    let (subscription, rx) = capture(&flashlight_a);
+    flashlight_a
+        .set_monster_control(MonsterControl::Spectator)
+        .unwrap();
+    drop(subscription);
+    for delta in rx.try_iter() {
+        assert_eq!(
+            spectator.apply_delta(&delta, Role::Player, 1),
+            Err(FlashlightError::NotYourTurn)
+        );
+        spectator.apply_delta(&delta, Role::Player, 0).unwrap();
+    }
+
+    let expected_map: MapState = "P.G........P....".into();
+    assert_eq!(spectator.get_map_glyphs(), expected_map.state);
+    assert_eq!(spectator.monster_control(), MonsterControl::Spectator);
+}
+
+#[test]
+fn resync_after_missing_a_delta() {
+    // . P . .
+    // . . . X
+    let starting_map: MapState = ".P.....X".into();
+
+    let mut flashlight_a = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Player);
+    let mut flashlight_b = Flashlight::new(starting_map.state.clone(), 4, 40, 4, Role::Spectator);
+
+    flashlight_b
+        .apply_initial_state_vector(&flashlight_a.encode_doc())
+        .unwrap();
+
+    // b never hears about these
+    for cell in [Vec2(2, 0), Vec2(2, 1)] {
+        assert_eq!(flashlight_a.do_move_player(cell), Ok(MoveOutcome::Advance));
+    }
+
+    // b asked for the whole map again after refusing the next delta
+    flashlight_b
+        .apply_initial_state_vector(&flashlight_a.encode_doc())
+        .unwrap();
+
+    assert_eq!(flashlight_b.get_map_glyphs(), flashlight_a.get_map_glyphs());
+    assert_eq!(flashlight_b.map_metadata().player_cell_idx, 6);
+    assert_eq!(flashlight_b.player_facing(), Direction::Down);
+}
+
+#[test]
+fn refuse_moves_after_game_over() {
+    // P . . G
+    let starting_map: MapState = "P..G".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
+    flashlight.player_poise = 0;
+
+    assert_eq!(
+        flashlight.do_move_player(Vec2::new_with_data(1, 0)),
+        Err(FlashlightError::GameOver)
+    );
+    assert_eq!(flashlight.get_map_glyphs(), starting_map.state);
+}
+
+#[test]
+fn round_trip_snapshot() {
+    // _ P * _
+    // _ . T _
+    // T . . .
+    // T * * X
+    // . * * .
+    let starting_map: MapState = "_P*__.T_T...T**X.**.".into();
+
+    let mut flashlight = Flashlight::new(starting_map.state.to_vec(), 4, 40, 4, Role::Player);
+    flashlight.visibility_mode = VisibilityMode::Cone;
+    flashlight.compute_visibility();
+    for cell in [Vec2(1, 1), Vec2(1, 2), Vec2(0, 2)] {
+        let _ = flashlight.do_move_player(cell);
+    }
+    flashlight.toggle_flashlight();
+    flashlight.compute_visibility();
+
+    let mut restored = Flashlight::restore(&flashlight.snapshot()).unwrap();
+
+    assert_eq!(restored.get_map_glyphs(), flashlight.get_map_glyphs());
+    assert_eq!(restored.turn(), 3);
+    assert_eq!(restored.player_poise, flashlight.player_poise);
+    assert_eq!(restored.monster_poise, flashlight.monster_poise);
+    assert_eq!(restored.battery(), flashlight.battery());
+    assert!(!restored.is_flashlight_on());
+    assert_eq!(restored.visibility_mode, VisibilityMode::Cone);
+    assert_eq!(restored.explored_tiles, flashlight.explored_tiles);
+    assert_eq!(restored.player_facing(), Direction::Left);
+    assert_eq!(
+        restored.map_metadata().player_cell_idx,
+        flashlight.map_metadata().player_cell_idx
+    );
+
+    // both pick up from the same place
+    restored.compute_visibility();
+    assert_eq!(
+        restored.get_clipped_tile_visibility(),
+        flashlight.get_clipped_tile_visibility()
+    );
+    assert_eq!(
+        restored.do_move_player(Vec2(2, 2)),
+        Ok(MoveOutcome::Advance)
+    );
+    assert_eq!(
+        flashlight.do_move_player(Vec2(2, 2)),
+        Ok(MoveOutcome::Advance)
+    );
+    assert_eq!(restored.get_map_glyphs(), flashlight.get_map_glyphs());
+}
+
+#[test]
+fn restore_snapshots_from_other_versions() {
+    let starting_map: MapState = ".P.....X".into();
+    let flashlight = Flashlight::new(starting_map.state.clone(), 4, 40, 2, Role::Spectator);
+    let snapshot: serde_json::Value = serde_json::from_slice(&flashlight.snapshot()).unwrap();
+
+    // fields added by newer engines are skipped
+    let mut with_new_field = snapshot.clone();
+    with_new_field["addedLater"] = serde_json::json!(true);
+    let restored = Flashlight::restore(&serde_json::to_vec(&with_new_field).unwrap()).unwrap();
+    assert_eq!(restored.role, Role::Spectator);
+    assert_eq!(restored.get_map_glyphs(), starting_map.state);
+
+    let mut newer = snapshot.clone();
+    newer["version"] = serde_json::json!(SNAPSHOT_VERSION + 1);
+    assert_eq!(
+        Flashlight::restore(&serde_json::to_vec(&newer).unwrap()).err(),
+        Some(FlashlightError::DecodeFailed)
+    );
+    assert_eq!(
+        Flashlight::restore(b"not a snapshot").err(),
+        Some(FlashlightError::DecodeFailed)
+    );
+}
+
+#[test]
+fn reject_snapshots_with_mismatched_dimensions() {
+    // . P . .
+    // . . . X
+    let starting_map: MapState = ".P.....X".into();
+    let flashlight = Flashlight::new(starting_map.state.clone(), 4, 40, 2, Role::Player);
+    let snapshot: serde_json::Value = serde_json::from_slice(&flashlight.snapshot()).unwrap();
+    let restore_with = |field: &str, value: u8| {
+        let mut tampered = snapshot.clone();
+        tampered[field] = serde_json::json!(value);
+        Flashlight::restore(&serde_json::to_vec(&tampered).unwrap()).err()
+    };
+
+    assert!(Flashlight::restore(&flashlight.snapshot()).is_ok());
+    assert_eq!(
+        restore_with("width", 0),
+        Some(FlashlightError::DecodeFailed)
+    );
+    assert_eq!(
+        restore_with("width", 3),
+        Some(FlashlightError::DecodeFailed)
+    );
+    assert_eq!(
+        restore_with("viewWidth", 0),
+        Some(FlashlightError::DecodeFailed)
+    );
+    // wider than the map
+    assert_eq!(
+        restore_with("viewWidth", 5),
+        Some(FlashlightError::DecodeFailed)
+    );
+    // taller than the map
+    assert_eq!(
+        restore_with("viewWidth", 3),
+        Some(FlashlightError::DecodeFailed)
+    );
+}
+
+#[test]
+fn reject_snapshots_with_players_off_their_cells() {
+    // . P . .
+    // . . . X
+    let starting_map: MapState = ".P.....X".into();
+    let flashlight = Flashlight::new(starting_map.state.clone(), 4, 40, 2, Role::Player);
+    {
+        let player_state = flashlight.map_state_doc.get_or_insert_map("player_state");
+        let mut txn = flashlight.map_state_doc.transact_mut();
+        player_state.insert(&mut txn, player_cell_key(0), 0u16);
+    }
+
+    assert_eq!(
+        Flashlight::restore(&flashlight.snapshot()).err(),
+        Some(FlashlightError::IllegalMove)
+    );
+}
+
+#[test]
+fn reject_snapshots_taller_than_a_map_can_be() {
+    let starting_map: MapState = "P...".into();
+    let flashlight = Flashlight::new(starting_map.state, 2, 40, 2, Role::Spectator);
+    let mut snapshot: serde_json::Value = serde_json::from_slice(&flashlight.snapshot()).unwrap();
+
+    // two columns of 256 cells
+    let doc = Doc::new();
+    let map_state = doc.get_or_insert_text("map_state");
+    {
+        let mut txn = doc.transact_mut();
+        map_state.insert(&mut txn, 0, &format!("P{}", ".".repeat(511)));
+    }
+    snapshot["doc"] = serde_json::json!(
+        doc.transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default())
+    );
+    snapshot["exploredTiles"] = serde_json::json!(vec![false; 512]);
+
+    assert_eq!(
+        Flashlight::restore(&serde_json::to_vec(&snapshot).unwrap()).err(),
+        Some(FlashlightError::DecodeFailed)
+    );
+}
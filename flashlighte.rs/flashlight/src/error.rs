@@ -0,0 +1,48 @@
+use crate::prelude::*;
+
+/// Why the engine refused a call, thrown as the variant on the JS side
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum FlashlightError {
+    /// not a yrs update, or one that can't be integrated into the doc
+    DecodeFailed,
+    /// a map with a character that isn't a glyph
+    InvalidGlyph,
+    /// a cell outside the map
+    OutOfBounds,
+    /// a move the character can't make, like skipping cells
+    IllegalMove,
+    /// the character is controlled by someone else
+    NotYourTurn,
+    /// the player or the monster has run out of poise
+    GameOver,
+}
+
+impl fmt::Display for FlashlightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            FlashlightError::DecodeFailed => "update could not be decoded",
+            FlashlightError::InvalidGlyph => "map contains an unknown glyph",
+            FlashlightError::OutOfBounds => "cell is outside the map",
+            FlashlightError::IllegalMove => "move is not allowed",
+            FlashlightError::NotYourTurn => "character is controlled by another peer",
+            FlashlightError::GameOver => "game is over",
+        };
+
+        write!(f, "{message}")
+    }
+}
+
+impl std::error::Error for FlashlightError {}
+
+impl From<InvalidGlyph> for FlashlightError {
+    fn from(_: InvalidGlyph) -> Self {
+        FlashlightError::InvalidGlyph
+    }
+}
+
+impl From<TransitionError> for FlashlightError {
+    fn from(_: TransitionError) -> Self {
+        FlashlightError::IllegalMove
+    }
+}
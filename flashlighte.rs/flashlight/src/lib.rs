@@ -1,5 +1,7 @@
 mod camera;
 mod engine;
+mod error;
+mod snapshot;
 
 pub mod prelude {
     pub use core::fmt;
@@ -7,8 +9,8 @@ pub mod prelude {
 
     pub use wasm_bindgen::prelude::*;
     pub use yrs::{
-        Doc, GetString, Observable, ReadTxn, Subscription, Text, TextRef, Transact, TransactionMut,
-        Update,
+        Any, Doc, GetString, Map, MapRef, Observable, Out, ReadTxn, Subscription, Text, TextRef,
+        Transact, TransactionMut, Update,
         types::Delta,
         types::text::TextEvent,
         updates::decoder::Decode,
@@ -21,6 +23,8 @@ pub mod prelude {
 
     pub use crate::camera::*;
     pub use crate::engine::*;
+    pub use crate::error::*;
+    pub use crate::snapshot::SNAPSHOT_VERSION;
 }
 
 pub use prelude::*;
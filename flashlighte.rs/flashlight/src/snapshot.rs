@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+
+/// Bumped when snapshots change in a way older engines can't read.
+///
+/// Adding a field doesn't need a bump: older engines skip fields they don't know,
+/// newer ones should give the field a `#[serde(default)]` for snapshots that predate it.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A game in progress, stored as JSON
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Snapshot {
+    pub version: u32,
+    /// the shared doc as a yrs update, holds the map and every player's cell, facing and flashlight
+    pub doc: Vec<u8>,
+    pub role: Role,
+    pub spectator_view: SpectatorView,
+    pub width: u8,
+    pub view_width: u8,
+    pub cell_width: u8,
+    pub player_index: u8,
+    pub player_poise: u8,
+    pub monster_poise: u8,
+    pub visibility_mode: VisibilityMode,
+    pub cone_angle: u16,
+    /// tiles that have been visible at least once, indexed like the map
+    pub explored_tiles: Vec<bool>,
+    pub turn: u32,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // only plain data in here, serializing can't fail
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+
+    /// Reads a snapshot written by this or an older engine.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FlashlightError> {
+        let snapshot: Snapshot =
+            serde_json::from_slice(bytes).map_err(|_| FlashlightError::DecodeFailed)?;
+
+        match snapshot.version <= SNAPSHOT_VERSION {
+            true => Ok(snapshot),
+            false => Err(FlashlightError::DecodeFailed),
+        }
+    }
+}
@@ -2,33 +2,33 @@ use crate::prelude::*;
 
 const POTENTIAL_DELTAS: &[Vec2; 4] = &[Vec2(-1, 0), Vec2(0, -1), Vec2(1, 0), Vec2(0, 1)];
 
-fn get_candidates(map_state: &Vec<Glyph>, width: u8, curr_pos_idx: u16) -> Vec<u16> {
+fn get_candidates(map_state: &[Glyph], width: u8, curr_pos_idx: u16) -> Vec<u16> {
     // at len = 256 casting as u8 fails
     let height = ((map_state.len()) / (width as usize)) as u8;
 
     let curr_pos = idx_to_grid_position(curr_pos_idx, width);
-    return POTENTIAL_DELTAS
+    POTENTIAL_DELTAS
         .iter()
         .map(|pd| Vec2::new_with_data(curr_pos.0 + pd.0, curr_pos.1 + pd.1))
         .filter(|candidate| {
-            if !is_in_bounds(&candidate, width, height) {
+            if !is_in_bounds(candidate, width, height) {
                 return false;
             }
 
             let candidate_idx = grid_position_to_idx(*candidate, width);
             let glyph = map_state.get(candidate_idx as usize);
             if let Some(glyph) = glyph {
-                return is_in_bounds(&candidate, width, height) && glyph.is_targetable();
+                is_in_bounds(candidate, width, height) && glyph.is_targetable()
             } else {
-                return false;
+                false
             }
         })
         .map(|c| grid_position_to_idx(c, width))
-        .collect();
+        .collect()
 }
 
 fn find_path_u16(
-    starting_map: &Vec<Glyph>,
+    starting_map: &[Glyph],
     width: u8,
     from_glyph: Glyph,
     to_glyph: Glyph,
@@ -69,7 +69,7 @@ fn find_path_u16(
     // Initialize BFS queue with `cell_idx`s
     bfs_queue.push_back(grid_position_to_idx(glyph_cell, width));
 
-    while bfs_queue.len() > 0 {
+    while !bfs_queue.is_empty() {
         let curr = bfs_queue.pop_front();
 
         if let Some(curr) = curr {
@@ -91,7 +91,7 @@ fn find_path_u16(
                 return Vec::from(path);
             }
 
-            let curr_neighbors = get_candidates(&starting_map, width, curr);
+            let curr_neighbors = get_candidates(starting_map, width, curr);
             for neighbor in curr_neighbors {
                 if !visited_cell_idx_cache.contains(&neighbor) {
                     visited_cell_idx_cache.insert(neighbor);
@@ -102,11 +102,11 @@ fn find_path_u16(
         }
     }
 
-    return vec![];
+    vec![]
 }
 
 pub fn find_path(
-    starting_map_data: &Vec<Glyph>,
+    starting_map_data: &[Glyph],
     width: u8,
     from_glyph: Glyph,
     to_glyph: Glyph,
@@ -129,26 +129,30 @@ pub fn find_path(
         })
         .collect();
 
-    return sol;
+    sol
+}
+
+#[cfg(test)]
+fn glyphs(map: &str) -> Vec<Glyph> {
+    map.bytes()
+        .map(|glyph| Glyph::try_from(glyph).unwrap())
+        .collect()
 }
 
 #[test]
 fn find_shortest_path_from_player_to_target() {
-    let starting_map: Vec<Glyph> = "_PT__.._TT..TTTXTTTT".chars().map(Glyph::from).collect();
+    let starting_map: Vec<Glyph> = glyphs("_PT__.._TT..TTTXTTTT");
     let shortest_path = find_path(&starting_map, 4, Glyph::Player, Glyph::Target);
     // `to` and `from` are flattened into a single array
     assert_eq!(shortest_path.len(), 5);
 
     // `to` and `from` are flattened into a single array
-    let shortest_path = find_path(
-        &"P.X".chars().map(Glyph::from).collect(),
-        3,
-        Glyph::Player,
-        Glyph::Target,
-    );
+    let shortest_path = find_path(&glyphs("P.X"), 3, Glyph::Player, Glyph::Target);
     assert_eq!(shortest_path.len(), 2);
 
-    let st = ".P....TT.............T......T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T........T......T........T......T.........X..".chars().map(Glyph::from).collect();
+    let st: Vec<Glyph> = glyphs(
+        ".P....TT.............T......T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T........T......T........T......T.........X..",
+    );
     let shortest_path = find_path(&st, 16, Glyph::Player, Glyph::Target);
     assert_eq!(shortest_path.len(), 27);
 }
@@ -156,7 +160,7 @@ fn find_shortest_path_from_player_to_target() {
 #[test]
 fn find_shortest_path_from_monster_to_target() {
     let shortest_path = find_path(
-        &"_GT__.._TT..TTTPTTTT".chars().map(Glyph::from).collect(),
+        &glyphs("_GT__.._TT..TTTPTTTT"),
         4,
         Glyph::Monster,
         Glyph::Player,
@@ -165,24 +169,16 @@ fn find_shortest_path_from_monster_to_target() {
     assert_eq!(shortest_path.len(), 5);
 
     // `to` and `from` are flattened into a single array
-    let shortest_path = find_path(
-        &"G.P".chars().map(Glyph::from).collect(),
-        3,
-        Glyph::Monster,
-        Glyph::Player,
-    );
+    let shortest_path = find_path(&glyphs("G.P"), 3, Glyph::Monster, Glyph::Player);
     assert_eq!(shortest_path.len(), 2);
 
     // `to` and `from` are flattened into a single array
-    let shortest_path = find_path(
-        &"GP".chars().map(Glyph::from).collect(),
-        2,
-        Glyph::Monster,
-        Glyph::Player,
-    );
+    let shortest_path = find_path(&glyphs("GP"), 2, Glyph::Monster, Glyph::Player);
     assert_eq!(shortest_path.len(), 1);
 
-    let st = ".G....TT.............T......T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T........T......T........T......T.........P..".chars().map(Glyph::from).collect();
+    let st: Vec<Glyph> = glyphs(
+        ".G....TT.............T......T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T....T...T......T........T......T........T......T.........P..",
+    );
     let shortest_path = find_path(&st, 16, Glyph::Monster, Glyph::Player);
     assert_eq!(shortest_path.len(), 27);
 }
@@ -0,0 +1,48 @@
+//! Runs the server without Shuttle, configured from env vars and an optional TOML file.
+//!
+//! `cargo run --no-default-features --features standalone --bin standalone`
+
+use flashes_server::config::{ServerConfig, CONFIG_PATH_VAR};
+use flashes_server::{build_router, build_state, shutdown};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt::init();
+
+    let contents = match std::env::var(CONFIG_PATH_VAR) {
+        Ok(path) => Some(std::fs::read_to_string(path)?),
+        Err(_) => None,
+    };
+    let config = ServerConfig::from_toml_and_env(contents.as_deref())?;
+    let state = build_state(&config)?;
+
+    let listener = tokio::net::TcpListener::bind(config.bind_address).await?;
+    tracing::info!("listening on {}", listener.local_addr()?);
+    let router = build_router(state.clone(), &config);
+    shutdown::serve_until(listener, router, state, shutdown_signal()).await?;
+
+    Ok(())
+}
+
+/// Ctrl-C, or SIGTERM from whatever is redeploying us.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
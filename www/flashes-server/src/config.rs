@@ -0,0 +1,238 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::game::SessionConfig;
+use crate::origins::OriginAllowlist;
+use crate::tokens::{ResumeTokens, DEFAULT_RESUME_TOKEN_TTL};
+
+pub const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:8000";
+/// env var pointing the standalone server at a TOML config file
+pub const CONFIG_PATH_VAR: &str = "FLASHES_CONFIG";
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// ignored on Shuttle, which binds for us
+    pub bind_address: SocketAddr,
+    /// checked on CORS requests and websocket upgrades
+    pub allowed_origins: OriginAllowlist,
+    /// `*.json` maps added to the built-in ones
+    pub map_dir: Option<PathBuf>,
+    /// JSON lines file finished matches are appended to, kept in memory when missing
+    pub match_log: Option<PathBuf>,
+    /// bearer token for the `/admin` routes, which are left out when it's missing
+    pub admin_token: Option<String>,
+    pub session: SessionConfig,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    Missing(&'static str),
+    Invalid { key: &'static str, value: String },
+    Toml(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing(key) => write!(f, "{} must be set", key),
+            ConfigError::Invalid { key, value } => write!(f, "invalid {}: {}", key, value),
+            ConfigError::Toml(reason) => write!(f, "invalid config file: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ServerConfig {
+    /// Reads every setting through `lookup`, keyed by upper case names like `ALLOWED_ORIGINS`.
+    ///
+    /// Missing settings fall back to their defaults, except for the allowed origins.
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let defaults = SessionConfig::default();
+
+        let allowed_origins: Vec<String> = lookup("ALLOWED_ORIGINS")
+            // the name Shuttle deployments were set up with
+            .or_else(|| lookup("ALLOWED_ORIGIN"))
+            .ok_or(ConfigError::Missing("ALLOWED_ORIGINS"))?
+            .split(',')
+            .map(|origin| origin.trim().to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        let allowed_origins =
+            OriginAllowlist::parse(&allowed_origins).map_err(|reason| ConfigError::Invalid {
+                key: "ALLOWED_ORIGINS",
+                value: reason,
+            })?;
+
+        let session = SessionConfig {
+            player_slots: parse(&lookup, "PLAYER_SLOTS")?.unwrap_or(defaults.player_slots),
+            spectator_slots: parse(&lookup, "SPECTATOR_SLOTS")?.unwrap_or(defaults.spectator_slots),
+            reconnect_grace: parse(&lookup, "RECONNECT_GRACE_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.reconnect_grace),
+            promote_spectators: parse(&lookup, "PROMOTE_SPECTATORS")?
+                .unwrap_or(defaults.promote_spectators),
+            // without a fixed secret, resume tokens stop working after a restart
+            resume_tokens: lookup("RESUME_TOKEN_SECRET")
+                .map(|secret| ResumeTokens::new(secret, DEFAULT_RESUME_TOKEN_TTL))
+                .unwrap_or(defaults.resume_tokens),
+            report_tokens: defaults.report_tokens,
+            heartbeat_interval: parse(&lookup, "HEARTBEAT_INTERVAL_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.heartbeat_interval),
+            idle_timeout: parse(&lookup, "IDLE_TIMEOUT_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.idle_timeout),
+            max_message_bytes: parse(&lookup, "MAX_MESSAGE_BYTES")?
+                .unwrap_or(defaults.max_message_bytes),
+            messages_per_second: parse(&lookup, "MESSAGES_PER_SECOND")?
+                .unwrap_or(defaults.messages_per_second),
+            message_burst: parse(&lookup, "MESSAGE_BURST")?.unwrap_or(defaults.message_burst),
+            outbound_queue: parse(&lookup, "OUTBOUND_QUEUE")?.unwrap_or(defaults.outbound_queue),
+            shutdown_reconnect_after: parse(&lookup, "SHUTDOWN_RECONNECT_AFTER_MS")?
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.shutdown_reconnect_after),
+            shutdown_drain_timeout: parse(&lookup, "SHUTDOWN_DRAIN_TIMEOUT_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.shutdown_drain_timeout),
+        };
+
+        Ok(Self {
+            bind_address: parse(&lookup, "BIND_ADDRESS")?
+                .unwrap_or_else(|| DEFAULT_BIND_ADDRESS.parse().expect("default is valid")),
+            allowed_origins,
+            map_dir: lookup("MAP_DIR").map(PathBuf::from),
+            match_log: lookup("MATCH_LOG").map(PathBuf::from),
+            admin_token: lookup("ADMIN_TOKEN").filter(|token| !token.is_empty()),
+            session,
+        })
+    }
+
+    /// Settings from a TOML file, with env vars taking precedence.
+    ///
+    /// File keys are the lower case setting names, lists are TOML arrays.
+    pub fn from_toml_and_env(contents: Option<&str>) -> Result<Self, ConfigError> {
+        let table = match contents {
+            Some(contents) => contents
+                .parse::<toml::Table>()
+                .map_err(|e| ConfigError::Toml(e.to_string()))?,
+            None => toml::Table::new(),
+        };
+
+        Self::from_lookup(|key| {
+            std::env::var(key)
+                .ok()
+                .or_else(|| table.get(&key.to_lowercase()).map(toml_to_setting))
+        })
+    }
+}
+
+fn parse<T: FromStr>(
+    lookup: &impl Fn(&str) -> Option<String>,
+    key: &'static str,
+) -> Result<Option<T>, ConfigError> {
+    lookup(key)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| ConfigError::Invalid { key, value })
+        })
+        .transpose()
+}
+
+/// Flattens a TOML value into the same string an env var would hold.
+fn toml_to_setting(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(value) => value.clone(),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(toml_to_setting)
+            .collect::<Vec<_>>()
+            .join(","),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+fn lookup_from(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let pairs: Vec<(String, String)> = pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    move |key| {
+        pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.clone())
+    }
+}
+
+#[test]
+fn read_config_with_defaults() {
+    let config = ServerConfig::from_lookup(lookup_from(&[
+        ("ALLOWED_ORIGINS", "https://a.example, https://b.example"),
+        ("PLAYER_SLOTS", "2"),
+    ]))
+    .unwrap();
+
+    assert!(config.allowed_origins.allows("https://a.example"));
+    assert!(config.allowed_origins.allows("https://b.example"));
+    assert_eq!(config.bind_address, DEFAULT_BIND_ADDRESS.parse().unwrap());
+    assert_eq!(config.map_dir, None);
+    assert_eq!(config.admin_token, None);
+    assert_eq!(config.session.player_slots, 2);
+    assert_eq!(
+        config.session.reconnect_grace,
+        SessionConfig::default().reconnect_grace
+    );
+}
+
+#[test]
+fn reject_missing_or_invalid_settings() {
+    assert_eq!(
+        ServerConfig::from_lookup(lookup_from(&[])).unwrap_err(),
+        ConfigError::Missing("ALLOWED_ORIGINS")
+    );
+    assert!(matches!(
+        ServerConfig::from_lookup(lookup_from(&[("ALLOWED_ORIGINS", "a.example")])).unwrap_err(),
+        ConfigError::Invalid {
+            key: "ALLOWED_ORIGINS",
+            ..
+        }
+    ));
+    assert_eq!(
+        ServerConfig::from_lookup(lookup_from(&[
+            ("ALLOWED_ORIGIN", "https://a.example"),
+            ("BIND_ADDRESS", "nowhere"),
+        ]))
+        .unwrap_err(),
+        ConfigError::Invalid {
+            key: "BIND_ADDRESS",
+            value: "nowhere".to_string()
+        }
+    );
+}
+
+#[test]
+fn read_config_from_toml() {
+    let config = ServerConfig::from_toml_and_env(Some(
+        r#"
+        bind_address = "127.0.0.1:9000"
+        allowed_origins = ["https://a.example", "https://b.example"]
+        map_dir = "maps"
+        idle_timeout_secs = 5
+        promote_spectators = false
+        "#,
+    ))
+    .unwrap();
+
+    assert_eq!(config.bind_address, "127.0.0.1:9000".parse().unwrap());
+    assert!(config.allowed_origins.allows("https://b.example"));
+    assert_eq!(config.map_dir, Some(PathBuf::from("maps")));
+    assert_eq!(config.session.idle_timeout, Duration::from_secs(5));
+    assert!(!config.session.promote_spectators);
+}
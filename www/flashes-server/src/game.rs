@@ -1,10 +1,27 @@
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Notify;
 
+use crate::history::MATCH_REPORT_TTL;
 use crate::maps::{get_default_map, GameMap};
+use crate::messages::{Feature, ServerMessage};
+use crate::tokens::ResumeTokens;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub const DEFAULT_PLAYER_SLOTS: usize = 1;
+pub const DEFAULT_SPECTATOR_SLOTS: usize = 1;
+pub const DEFAULT_RECONNECT_GRACE: Duration = Duration::from_secs(10);
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 64 * 1024;
+pub const DEFAULT_MESSAGES_PER_SECOND: u32 = 20;
+pub const DEFAULT_MESSAGE_BURST: u32 = 40;
+pub const DEFAULT_OUTBOUND_QUEUE: usize = 64;
+pub const DEFAULT_SHUTDOWN_RECONNECT_AFTER: Duration = Duration::from_secs(5);
+pub const DEFAULT_SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum Role {
     Player,
     Spectator,
@@ -13,24 +30,595 @@ pub enum Role {
 #[derive(Debug, Clone)]
 pub struct Client {
     pub id: String,
-    pub sender: mpsc::UnboundedSender<String>,
+    /// encoded by the connection, in whatever it negotiated
+    pub sender: ClientSender,
+    /// negotiated when the client joined
+    pub features: Vec<Feature>,
+    /// when the websocket was opened, kept across role changes
+    pub connected_at: Instant,
+}
+
+/// Bounded queue of messages on their way to one connection.
+///
+/// A client that doesn't keep up is disconnected instead of the queue growing without limit.
+/// Every clone shares the queue, so closing one closes the connection for all of them.
+#[derive(Debug, Clone)]
+pub struct ClientSender {
+    queue: mpsc::Sender<ServerMessage>,
+    closed: Arc<Notify>,
+}
+
+impl ClientSender {
+    pub fn new(capacity: usize) -> (Self, mpsc::Receiver<ServerMessage>) {
+        let (queue, receiver) = mpsc::channel(capacity);
+        let sender = Self {
+            queue,
+            closed: Arc::new(Notify::new()),
+        };
+
+        (sender, receiver)
+    }
+
+    /// Queues the message, returns `false` if it was dropped.
+    pub fn send(&self, message: ServerMessage) -> bool {
+        match self.queue.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.closed.notify_one();
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Nothing is left waiting to be written.
+    pub fn is_drained(&self) -> bool {
+        self.queue.capacity() == self.queue.max_capacity()
+    }
+
+    /// Asks the connection to disconnect.
+    pub fn close(&self) {
+        self.closed.notify_one();
+    }
+
+    /// Resolves once the connection should be dropped, because a message didn't fit
+    /// in the queue or it was closed.
+    pub async fn closed(&self) {
+        self.closed.notified().await
+    }
+}
+
+impl Client {
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Slot {
+    Free,
+    Taken(Client),
+    /// held for a disconnected client until `expires_at`
+    Reserved {
+        client_id: String,
+        expires_at: Instant,
+    },
+}
+
+impl Slot {
+    fn client(&self) -> Option<&Client> {
+        match self {
+            Slot::Taken(client) => Some(client),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// capped by the number of `P`s on the map
+    pub player_slots: usize,
+    pub spectator_slots: usize,
+    /// how long a disconnected client's slot is held for them, zero disables reservations
+    pub reconnect_grace: Duration,
+    /// move a spectator into a free player slot
+    pub promote_spectators: bool,
+    pub resume_tokens: ResumeTokens,
+    /// signs the tokens players report match outcomes with, apart from resume tokens
+    /// so every client gets one whatever features it negotiated
+    pub report_tokens: ResumeTokens,
+    /// how often the server pings each connection
+    pub heartbeat_interval: Duration,
+    /// connections that send nothing, not even a pong, for this long are dropped
+    pub idle_timeout: Duration,
+    /// larger frames close the connection
+    pub max_message_bytes: usize,
+    /// messages past the rate are refused with a `RateLimited` error
+    pub messages_per_second: u32,
+    pub message_burst: u32,
+    /// messages waiting to be written to a connection before it's dropped
+    pub outbound_queue: usize,
+    /// how long clients are told to wait before reconnecting when the server shuts down
+    pub shutdown_reconnect_after: Duration,
+    /// how long shutting down waits for outbound queues to empty
+    pub shutdown_drain_timeout: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            player_slots: DEFAULT_PLAYER_SLOTS,
+            spectator_slots: DEFAULT_SPECTATOR_SLOTS,
+            reconnect_grace: DEFAULT_RECONNECT_GRACE,
+            promote_spectators: true,
+            resume_tokens: ResumeTokens::default(),
+            // pending matches don't survive a restart either
+            report_tokens: ResumeTokens::random(MATCH_REPORT_TTL),
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            messages_per_second: DEFAULT_MESSAGES_PER_SECOND,
+            message_burst: DEFAULT_MESSAGE_BURST,
+            outbound_queue: DEFAULT_OUTBOUND_QUEUE,
+            shutdown_reconnect_after: DEFAULT_SHUTDOWN_RECONNECT_AFTER,
+            shutdown_drain_timeout: DEFAULT_SHUTDOWN_DRAIN_TIMEOUT,
+        }
+    }
+}
+
+/// A spectator that was moved into a player slot
+#[derive(Debug, Clone)]
+pub struct Promotion {
+    pub client: Client,
+    pub slot: usize,
 }
 
 #[derive(Debug)]
 pub struct GameSession {
-    pub player: Option<Client>,
-    pub spectator: Option<Client>,
+    /// one slot per `P` on the map
+    pub players: Vec<Slot>,
+    pub spectators: Vec<Slot>,
     pub map: GameMap,
+    pub config: SessionConfig,
 }
 
-impl Default for GameSession {
-    fn default() -> Self {
+impl GameSession {
+    pub fn new(config: SessionConfig) -> Self {
+        Self::with_map(config, get_default_map())
+    }
+
+    pub fn with_map(config: SessionConfig, map: GameMap) -> Self {
+        let player_slots = config.player_slots.min(map.player_count());
+
         Self {
-            player: None,
-            spectator: None,
-            map: get_default_map(),
+            players: vec![Slot::Free; player_slots],
+            spectators: vec![Slot::Free; config.spectator_slots],
+            map,
+            config,
+        }
+    }
+
+    /// Assigns the client to the first free slot, players go first.
+    ///
+    /// A client reconnecting within the grace period with the id they had before
+    /// gets their reserved slot back.
+    /// Returns the role and the slot index, or `None` if the session is full.
+    pub fn join(&mut self, client: Client, now: Instant) -> Option<(Role, usize)> {
+        self.expire_reservations(now);
+
+        for (role, slots) in [
+            (Role::Player, &mut self.players),
+            (Role::Spectator, &mut self.spectators),
+        ] {
+            let reserved_slot = slots.iter().position(
+                |slot| matches!(slot, Slot::Reserved { client_id, .. } if *client_id == client.id),
+            );
+
+            if let Some(slot) = reserved_slot {
+                slots[slot] = Slot::Taken(client);
+                return Some((role, slot));
+            }
+        }
+
+        if let Some(slot) = self.players.iter().position(|s| matches!(s, Slot::Free)) {
+            self.players[slot] = Slot::Taken(client);
+            return Some((Role::Player, slot));
+        }
+
+        if let Some(slot) = self.spectators.iter().position(|s| matches!(s, Slot::Free)) {
+            self.spectators[slot] = Slot::Taken(client);
+            return Some((Role::Spectator, slot));
+        }
+
+        None
+    }
+
+    /// Frees the slot held by the client, returns the role it had.
+    ///
+    /// Slots are reserved for the grace period instead of being freed.
+    pub fn leave(&mut self, client_id: &str, now: Instant) -> Option<Role> {
+        let reconnect_grace = self.config.reconnect_grace;
+
+        for (role, slots) in [
+            (Role::Player, &mut self.players),
+            (Role::Spectator, &mut self.spectators),
+        ] {
+            for slot in slots.iter_mut() {
+                if slot.client().is_some_and(|client| client.id == client_id) {
+                    *slot = match !reconnect_grace.is_zero() {
+                        true => Slot::Reserved {
+                            client_id: client_id.to_string(),
+                            expires_at: now + reconnect_grace,
+                        },
+                        false => Slot::Free,
+                    };
+                    return Some(role);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Frees the reservations that ran out, returns how many were freed.
+    pub fn expire_reservations(&mut self, now: Instant) -> usize {
+        let mut expired = 0;
+
+        for slot in self.players.iter_mut().chain(self.spectators.iter_mut()) {
+            if let Slot::Reserved { expires_at, .. } = slot {
+                if *expires_at <= now {
+                    *slot = Slot::Free;
+                    expired += 1;
+                }
+            }
+        }
+
+        expired
+    }
+
+    /// Moves the longest waiting spectator into the first free player slot,
+    /// if promotions are enabled.
+    ///
+    /// Spectators that can't be told about their new role are skipped.
+    pub fn promote_spectator(&mut self) -> Option<Promotion> {
+        if !self.config.promote_spectators {
+            return None;
+        }
+
+        let player_slot = self.players.iter().position(|s| matches!(s, Slot::Free))?;
+        let spectator_slot = self.spectators.iter().position(|s| {
+            s.client()
+                .is_some_and(|client| client.supports(Feature::RoleChange))
+        })?;
+
+        let Slot::Taken(client) =
+            std::mem::replace(&mut self.spectators[spectator_slot], Slot::Free)
+        else {
+            return None;
+        };
+        self.players[player_slot] = Slot::Taken(client.clone());
+
+        Some(Promotion {
+            client,
+            slot: player_slot,
+        })
+    }
+
+    /// Frees the client's slot right away, without holding it for a reconnect.
+    pub fn kick(&mut self, client_id: &str) -> Option<(Role, Client)> {
+        for (role, slots) in [
+            (Role::Player, &mut self.players),
+            (Role::Spectator, &mut self.spectators),
+        ] {
+            let Some(slot) = slots
+                .iter()
+                .position(|slot| slot.client().is_some_and(|client| client.id == client_id))
+            else {
+                continue;
+            };
+            if let Slot::Taken(client) = std::mem::replace(&mut slots[slot], Slot::Free) {
+                return Some((role, client));
+            }
         }
+
+        None
+    }
+
+    /// Swaps in a new map, dropping player slots past its `P`s.
+    ///
+    /// Returns the clients that were in the dropped slots.
+    pub fn reset_map(&mut self, map: GameMap) -> Vec<Client> {
+        let player_slots = self.config.player_slots.min(map.player_count());
+        self.map = map;
+
+        let dropped = match self.players.len() > player_slots {
+            true => self.players.split_off(player_slots),
+            false => Vec::new(),
+        };
+        self.players.resize(player_slots, Slot::Free);
+
+        dropped
+            .into_iter()
+            .filter_map(|slot| match slot {
+                Slot::Taken(client) => Some(client),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Role and slot index of a connected client.
+    pub fn slot_of(&self, client_id: &str) -> Option<(Role, usize)> {
+        [
+            (Role::Player, &self.players),
+            (Role::Spectator, &self.spectators),
+        ]
+        .into_iter()
+        .find_map(|(role, slots)| {
+            slots
+                .iter()
+                .position(|slot| slot.client().is_some_and(|client| client.id == client_id))
+                .map(|slot| (role, slot))
+        })
+    }
+
+    pub fn is_connected(&self, client_id: &str) -> bool {
+        self.clients().any(|(_, client)| client.id == client_id)
+    }
+
+    /// Every connected client along with their role.
+    pub fn clients(&self) -> impl Iterator<Item = (Role, &Client)> {
+        let players = self
+            .players
+            .iter()
+            .filter_map(Slot::client)
+            .map(|c| (Role::Player, c));
+        let spectators = self
+            .spectators
+            .iter()
+            .filter_map(Slot::client)
+            .map(|c| (Role::Spectator, c));
+
+        players.chain(spectators)
     }
 }
 
-pub type SharedState = Arc<Mutex<GameSession>>;
+impl Default for GameSession {
+    fn default() -> Self {
+        Self::new(SessionConfig::default())
+    }
+}
+
+#[cfg(test)]
+use crate::test_support::test_client;
+
+#[cfg(test)]
+fn test_session(reconnect_grace: Duration, promote_spectators: bool) -> GameSession {
+    GameSession::new(SessionConfig {
+        reconnect_grace,
+        promote_spectators,
+        ..SessionConfig::default()
+    })
+}
+
+#[test]
+fn assign_player_then_spectator() {
+    let mut session = test_session(Duration::ZERO, false);
+    let now = Instant::now();
+
+    assert_eq!(session.join(test_client("a"), now), Some((Role::Player, 0)));
+    assert_eq!(
+        session.join(test_client("b"), now),
+        Some((Role::Spectator, 0))
+    );
+    assert_eq!(session.join(test_client("c"), now), None);
+}
+
+#[test]
+fn free_slot_when_spectator_leaves() {
+    let mut session = test_session(Duration::ZERO, true);
+    let now = Instant::now();
+    session.join(test_client("a"), now);
+    session.join(test_client("b"), now);
+
+    assert_eq!(session.leave("b", now), Some(Role::Spectator));
+    assert!(matches!(session.spectators[0], Slot::Free));
+    assert!(session.promote_spectator().is_none());
+    assert_eq!(
+        session.join(test_client("c"), now),
+        Some((Role::Spectator, 0))
+    );
+}
+
+#[test]
+fn free_slot_when_player_leaves_without_grace() {
+    let mut session = test_session(Duration::ZERO, false);
+    let now = Instant::now();
+    session.join(test_client("a"), now);
+    session.join(test_client("b"), now);
+
+    assert_eq!(session.leave("a", now), Some(Role::Player));
+    assert!(matches!(session.players[0], Slot::Free));
+    assert!(session.promote_spectator().is_none());
+    assert_eq!(
+        session.clients().next().map(|(role, _)| role),
+        Some(Role::Spectator)
+    );
+}
+
+#[test]
+fn promote_spectator_when_player_leaves() {
+    let mut session = test_session(Duration::ZERO, true);
+    let now = Instant::now();
+    session.join(test_client("a"), now);
+    session.join(test_client("b"), now);
+
+    session.leave("a", now);
+    let promotion = session.promote_spectator().expect("spectator is promoted");
+
+    assert_eq!(promotion.client.id, "b");
+    assert_eq!(promotion.slot, 0);
+    assert!(matches!(session.spectators[0], Slot::Free));
+    assert_eq!(
+        session.join(test_client("c"), now),
+        Some((Role::Spectator, 0))
+    );
+}
+
+#[test]
+fn reserve_slot_for_disconnected_player() {
+    let mut session = test_session(DEFAULT_RECONNECT_GRACE, true);
+    let now = Instant::now();
+    session.join(test_client("a"), now);
+
+    session.leave("a", now);
+
+    assert!(matches!(session.players[0], Slot::Reserved { .. }));
+    assert!(session.promote_spectator().is_none());
+    // someone else can't take the slot
+    assert_eq!(
+        session.join(test_client("b"), now),
+        Some((Role::Spectator, 0))
+    );
+}
+
+#[test]
+fn reclaim_reserved_slot_within_grace() {
+    let mut session = test_session(DEFAULT_RECONNECT_GRACE, true);
+    let now = Instant::now();
+    session.join(test_client("a"), now);
+    session.leave("a", now);
+
+    let later = now + DEFAULT_RECONNECT_GRACE / 2;
+    assert_eq!(
+        session.join(test_client("a"), later),
+        Some((Role::Player, 0))
+    );
+    assert!(matches!(&session.players[0], Slot::Taken(client) if client.id == "a"));
+}
+
+#[test]
+fn expire_reservation_after_grace() {
+    let mut session = test_session(DEFAULT_RECONNECT_GRACE, true);
+    let now = Instant::now();
+    session.join(test_client("a"), now);
+    session.join(test_client("b"), now);
+    session.leave("a", now);
+
+    assert_eq!(session.expire_reservations(now), 0);
+
+    let later = now + DEFAULT_RECONNECT_GRACE;
+    assert_eq!(session.expire_reservations(later), 1);
+    assert!(matches!(session.players[0], Slot::Free));
+
+    let promotion = session.promote_spectator().expect("spectator is promoted");
+    assert_eq!(promotion.client.id, "b");
+
+    // too late to reclaim
+    assert_eq!(
+        session.join(test_client("a"), later),
+        Some((Role::Spectator, 0))
+    );
+}
+
+#[test]
+fn reserve_slot_for_disconnected_spectator() {
+    let mut session = test_session(DEFAULT_RECONNECT_GRACE, true);
+    let now = Instant::now();
+    session.join(test_client("a"), now);
+    session.join(test_client("b"), now);
+    session.join(test_client("c"), now);
+
+    assert_eq!(session.leave("b", now), Some(Role::Spectator));
+    assert!(matches!(session.spectators[0], Slot::Reserved { .. }));
+    assert!(!session.is_connected("b"));
+    assert_eq!(
+        session.join(test_client("b"), now),
+        Some((Role::Spectator, 0))
+    );
+    assert!(session.is_connected("b"));
+}
+
+#[test]
+fn skip_spectators_that_cannot_change_role() {
+    let mut session = test_session(Duration::ZERO, true);
+    let now = Instant::now();
+    session.join(test_client("a"), now);
+    session.join(
+        Client {
+            features: vec![],
+            ..test_client("b")
+        },
+        now,
+    );
+
+    session.leave("a", now);
+
+    assert!(session.promote_spectator().is_none());
+    assert!(matches!(session.players[0], Slot::Free));
+}
+
+#[test]
+fn flag_overflow_when_queue_is_full() {
+    let (sender, mut receiver) = ClientSender::new(2);
+    let message = || ServerMessage::PeerLeft {
+        peer_id: "a".to_string(),
+    };
+
+    assert!(sender.send(message()));
+    assert!(sender.send(message()));
+    assert!(!sender.send(message()));
+
+    // the overflow is remembered until someone waits for it
+    let closed = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(async {
+            tokio::time::timeout(Duration::ZERO, sender.closed())
+                .await
+                .is_ok()
+        });
+    assert!(closed);
+
+    assert!(receiver.try_recv().is_ok());
+    assert!(sender.send(message()));
+}
+
+#[test]
+fn kick_frees_slot_without_reservation() {
+    let mut session = test_session(DEFAULT_RECONNECT_GRACE, false);
+    let now = Instant::now();
+    session.join(test_client("a"), now);
+
+    assert!(matches!(session.kick("a"), Some((Role::Player, client)) if client.id == "a"));
+    assert!(matches!(session.players[0], Slot::Free));
+    assert!(session.kick("a").is_none());
+}
+
+#[test]
+fn reset_map_drops_extra_player_slots() {
+    let two_players = GameMap {
+        level: b"P..P".to_vec(),
+        width: 2,
+        cell_width: 40,
+        view_width: 2,
+    };
+    let mut session = GameSession::with_map(
+        SessionConfig {
+            player_slots: 2,
+            ..SessionConfig::default()
+        },
+        two_players,
+    );
+    let now = Instant::now();
+    session.join(test_client("a"), now);
+    session.join(test_client("b"), now);
+
+    let dropped = session.reset_map(get_default_map());
+    assert_eq!(session.players.len(), 1);
+    assert_eq!(
+        dropped.iter().map(|c| c.id.as_str()).collect::<Vec<_>>(),
+        ["b"]
+    );
+    assert!(session.is_connected("a"));
+}
@@ -0,0 +1,326 @@
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::info;
+
+use crate::game::{Role, Slot};
+use crate::handlers::websocket::announce_departure;
+use crate::lobby::{Room, RoomSummary, SharedState};
+use crate::messages::ServerMessage;
+
+type AdminError = (StatusCode, &'static str);
+
+/// What the admin endpoints show about a room, including who is in it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AdminRoom {
+    #[serde(flatten)]
+    pub summary: RoomSummary,
+    #[serde(rename = "ageSecs")]
+    pub age_secs: u64,
+    pub clients: Vec<AdminClient>,
+    /// slots held for clients that may still reconnect
+    #[serde(rename = "reservedSlots")]
+    pub reserved_slots: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AdminClient {
+    pub id: String,
+    pub role: Role,
+    #[serde(rename = "connectedSecs")]
+    pub connected_secs: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ResetMap {
+    /// a map from the catalog, the default map when missing
+    #[serde(default)]
+    pub map: Option<String>,
+}
+
+impl AdminRoom {
+    fn new(room: &Room, now: Instant) -> Self {
+        let session = &room.session;
+
+        Self {
+            summary: room.summary(),
+            age_secs: now.duration_since(room.created_at).as_secs(),
+            clients: session
+                .clients()
+                .map(|(role, client)| AdminClient {
+                    id: client.id.clone(),
+                    role,
+                    connected_secs: now.duration_since(client.connected_at).as_secs(),
+                })
+                .collect(),
+            reserved_slots: session
+                .players
+                .iter()
+                .chain(session.spectators.iter())
+                .filter(|slot| matches!(slot, Slot::Reserved { .. }))
+                .count(),
+        }
+    }
+}
+
+/// Routes for unsticking sessions by hand, every request needs `Authorization: Bearer <token>`.
+pub fn admin_router(token: &str) -> Router<SharedState> {
+    Router::new()
+        .route("/admin/rooms", get(list_rooms))
+        .route("/admin/rooms/{code}/map", post(reset_map))
+        .route("/admin/clients/{id}", delete(kick_client))
+        .route("/admin/shutdown", post(shutdown))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::<str>::from(token),
+            require_admin_token,
+        ))
+}
+
+async fn require_admin_token(
+    State(token): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()));
+
+    match authorized {
+        true => next.run(request).await,
+        false => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Compares without bailing out at the first difference, so timing doesn't leak the token.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Every room, full or not, with the clients in it.
+pub async fn list_rooms(State(state): State<SharedState>) -> Json<Vec<AdminRoom>> {
+    let lobby = state.lock().await;
+    let now = Instant::now();
+    Json(
+        lobby
+            .rooms
+            .iter()
+            .map(|room| AdminRoom::new(room, now))
+            .collect(),
+    )
+}
+
+/// Frees the client's slot and closes its connection.
+pub async fn kick_client(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    let mut lobby = state.lock().await;
+    let (code, client) = lobby
+        .rooms
+        .iter_mut()
+        .find_map(|room| {
+            let (_, client) = room.session.kick(&id)?;
+            announce_departure(&mut room.session, &id);
+            Some((room.code.clone(), client))
+        })
+        .ok_or((StatusCode::NOT_FOUND, "no client with that id"))?;
+
+    info!(client_id = %id, room = %code, "kicked by admin");
+    client.sender.close();
+    lobby.prune_empty_rooms(Instant::now());
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Puts a fresh copy of a catalog map in the room and tells everyone in it to start over.
+///
+/// Players in slots the new map has no `P` for are disconnected.
+pub async fn reset_map(
+    State(state): State<SharedState>,
+    Path(code): Path<String>,
+    Json(request): Json<ResetMap>,
+) -> Result<Json<AdminRoom>, AdminError> {
+    let mut lobby = state.lock().await;
+    let map = match &request.map {
+        Some(name) => lobby
+            .maps
+            .get(name)
+            .cloned()
+            .ok_or((StatusCode::NOT_FOUND, "no map with that name"))?,
+        None => lobby.default_map(),
+    };
+    let room = lobby
+        .room_mut(&code)
+        .ok_or((StatusCode::NOT_FOUND, "no room with that code"))?;
+
+    for client in room.session.reset_map(map) {
+        announce_departure(&mut room.session, &client.id);
+        client.sender.close();
+    }
+    for (_, client) in room.session.clients() {
+        client.sender.send(ServerMessage::MapReset {
+            map: room.session.map.clone(),
+        });
+    }
+    info!(room = %code, map = ?request.map, "map reset by admin");
+
+    Ok(Json(AdminRoom::new(room, Instant::now())))
+}
+
+/// Warns clients and turns away new connections ahead of a redeploy.
+///
+/// Shuttle exits as soon as it gets SIGTERM, so there the drain has to be started by hand.
+pub async fn shutdown(State(state): State<SharedState>) -> Json<serde_json::Value> {
+    let drained = crate::shutdown::drain(&state).await;
+    Json(serde_json::json!({ "drained": drained }))
+}
+
+#[cfg(test)]
+use crate::test_support::{send_request, state_with_room};
+
+#[tokio::test]
+async fn refuse_requests_without_the_token() {
+    let (state, _, _) = state_with_room(&[]);
+
+    let (status, _) = send_request(
+        admin_router("secret"),
+        &state,
+        "GET",
+        "/admin/rooms",
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send_request(
+        admin_router("secret"),
+        &state,
+        "GET",
+        "/admin/rooms",
+        Some("guess"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = send_request(
+        admin_router("secret"),
+        &state,
+        "GET",
+        "/admin/rooms",
+        Some("secret"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let rooms: Vec<AdminRoom> = serde_json::from_value(body.unwrap()).unwrap();
+    assert_eq!(rooms.len(), 1);
+}
+
+#[tokio::test]
+async fn kick_client_and_promote_spectator() {
+    let (state, code, clients) = state_with_room(&["a", "b"]);
+
+    let (status, _) = send_request(
+        admin_router("secret"),
+        &state,
+        "DELETE",
+        "/admin/clients/a",
+        Some("secret"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    // the kicked connection is told to close
+    assert!(
+        tokio::time::timeout(std::time::Duration::ZERO, clients[0].0.closed())
+            .await
+            .is_ok()
+    );
+
+    let (_, body) = send_request(
+        admin_router("secret"),
+        &state,
+        "GET",
+        "/admin/rooms",
+        Some("secret"),
+        None,
+    )
+    .await;
+    let rooms: Vec<AdminRoom> = serde_json::from_value(body.unwrap()).unwrap();
+    assert_eq!(rooms[0].summary.code, code);
+    assert_eq!(rooms[0].reserved_slots, 0);
+    assert!(matches!(
+        rooms[0].clients.as_slice(),
+        [AdminClient { id, role: Role::Player, .. }] if id == "b"
+    ));
+
+    let (status, _) = send_request(
+        admin_router("secret"),
+        &state,
+        "DELETE",
+        "/admin/clients/a",
+        Some("secret"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn reset_room_map_from_catalog() {
+    let (state, code, mut clients) = state_with_room(&["a"]);
+    let open_map = crate::maps::GameMap {
+        level: b"P.GX".to_vec(),
+        width: 2,
+        cell_width: 40,
+        view_width: 2,
+    };
+    state
+        .lock()
+        .await
+        .maps
+        .insert("open".to_string(), open_map.clone());
+
+    let uri = format!("/admin/rooms/{}/map", code);
+    let (status, _) = send_request(
+        admin_router("secret"),
+        &state,
+        "POST",
+        &uri,
+        Some("secret"),
+        Some(serde_json::json!({ "map": "open" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let lobby = state.lock().await;
+    assert_eq!(lobby.room(&code).unwrap().session.map.level, open_map.level);
+    drop(lobby);
+    // a stays in its slot and is told to start over on the new map
+    assert!(matches!(
+        clients[0].1.try_recv(),
+        Ok(ServerMessage::MapReset { map }) if map.level == open_map.level
+    ));
+
+    let (status, _) = send_request(
+        admin_router("secret"),
+        &state,
+        "POST",
+        &uri,
+        Some("secret"),
+        Some(serde_json::json!({ "map": "missing" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
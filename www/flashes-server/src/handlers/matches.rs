@@ -0,0 +1,264 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+use crate::game::Role;
+use crate::history::{record_matches, MatchQuery, MatchRecord, Outcome};
+use crate::lobby::SharedState;
+
+type MatchError = (StatusCode, &'static str);
+
+/// Sent by a player's client when its game ends
+#[derive(Debug, Deserialize)]
+pub struct ReportOutcome {
+    /// from `ClientAcknowledged`, proves which client and room the report is for
+    #[serde(rename = "reportToken")]
+    pub report_token: String,
+    pub outcome: Outcome,
+}
+
+pub fn matches_router() -> Router<SharedState> {
+    Router::new().route("/matches", get(list_matches).post(report_outcome))
+}
+
+/// Finished matches, newest first.
+pub async fn list_matches(
+    State(state): State<SharedState>,
+    Query(query): Query<MatchQuery>,
+) -> Result<Json<Vec<MatchRecord>>, MatchError> {
+    let store = state.lock().await.matches.clone();
+    // the file store reads the log from disk, keep it off the runtime
+    tokio::task::spawn_blocking(move || store.query(&query))
+        .await
+        .ok()
+        .and_then(|records| {
+            records
+                .inspect_err(|e| warn!(error = %e, "couldn't read matches"))
+                .ok()
+        })
+        .map(Json)
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "couldn't read matches"))
+}
+
+/// Ends the room's match with the outcome a player reported.
+pub async fn report_outcome(
+    State(state): State<SharedState>,
+    Json(report): Json<ReportOutcome>,
+) -> Result<(StatusCode, Json<MatchRecord>), MatchError> {
+    if report.outcome == Outcome::Abandoned {
+        return Err((StatusCode::BAD_REQUEST, "only Won or Lost can be reported"));
+    }
+
+    let now = SystemTime::now();
+    let mut lobby = state.lock().await;
+    let claims = lobby
+        .config
+        .report_tokens
+        .verify(&report.report_token, now)
+        .ok_or((StatusCode::UNAUTHORIZED, "invalid or expired token"))?;
+
+    // the newest of the client's matches in that room, older ones are left to expire
+    let index = lobby
+        .pending_matches
+        .iter()
+        .rposition(|pending| {
+            pending.room == claims.room
+                && pending
+                    .participants
+                    .iter()
+                    .any(|p| p.id == claims.client_id)
+        })
+        .ok_or((StatusCode::NOT_FOUND, "no match in progress in that room"))?;
+    let is_player = lobby.pending_matches[index]
+        .participants
+        .iter()
+        .any(|p| p.id == claims.client_id && p.role == Role::Player);
+    if !is_player {
+        return Err((StatusCode::FORBIDDEN, "only players can report an outcome"));
+    }
+
+    let record = lobby
+        .pending_matches
+        .remove(index)
+        .finish(report.outcome, now);
+    let store = lobby.matches.clone();
+    drop(lobby);
+
+    record_matches(store, vec![record.clone()])
+        .await
+        .map_err(|e| {
+            warn!(error = %e, "couldn't record match");
+            (StatusCode::INTERNAL_SERVER_ERROR, "couldn't record match")
+        })?;
+    info!(room = %record.room, outcome = ?record.outcome, "match recorded");
+
+    Ok((StatusCode::CREATED, Json(record)))
+}
+
+#[cfg(test)]
+use crate::test_support::send_request;
+
+#[cfg(test)]
+async fn state_with_match(client_ids: &[&str]) -> (SharedState, String) {
+    let (state, code, _) = crate::test_support::state_with_room(client_ids);
+    state.lock().await.track_match(&code, SystemTime::now());
+
+    (state, code)
+}
+
+#[tokio::test]
+async fn record_outcome_reported_by_player() {
+    let (state, code) = state_with_match(&["a", "b"]).await;
+    let token_for = |id: &str| {
+        let state = state.clone();
+        let (id, code) = (id.to_string(), code.clone());
+        async move {
+            let lobby = state.lock().await;
+            lobby
+                .config
+                .report_tokens
+                .issue(&id, &code, SystemTime::now())
+        }
+    };
+
+    // b joined as a spectator
+    let (status, _) = send_request(
+        matches_router(),
+        &state,
+        "POST",
+        "/matches",
+        None,
+        Some(serde_json::json!({ "reportToken": token_for("b").await, "outcome": "Won" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send_request(
+        matches_router(),
+        &state,
+        "POST",
+        "/matches",
+        None,
+        Some(serde_json::json!({ "reportToken": "forged", "outcome": "Won" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send_request(
+        matches_router(),
+        &state,
+        "POST",
+        "/matches",
+        None,
+        Some(serde_json::json!({ "reportToken": token_for("a").await, "outcome": "Won" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // the match is over, a second report has nothing to end
+    let (status, _) = send_request(
+        matches_router(),
+        &state,
+        "POST",
+        "/matches",
+        None,
+        Some(serde_json::json!({ "reportToken": token_for("a").await, "outcome": "Lost" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = send_request(
+        matches_router(),
+        &state,
+        "GET",
+        &format!("/matches?player=b&room={}", code),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let records: Vec<MatchRecord> = serde_json::from_value(body.unwrap()).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].outcome, Outcome::Won);
+    assert_eq!(
+        records[0]
+            .participants
+            .iter()
+            .map(|p| (p.id.as_str(), p.role))
+            .collect::<Vec<_>>(),
+        [("a", Role::Player), ("b", Role::Spectator)]
+    );
+}
+
+#[tokio::test]
+async fn record_unreported_matches_as_abandoned() {
+    let (state, _) = state_with_match(&["a", "b"]).await;
+    // a room with one client hasn't started a match
+    assert_eq!(state.lock().await.pending_matches.len(), 1);
+    assert_eq!(
+        crate::lobby::record_abandoned_matches(&state)
+            .await
+            .unwrap(),
+        0
+    );
+
+    let mut lobby = state.lock().await;
+    let earlier = SystemTime::now() - crate::history::MATCH_REPORT_TTL;
+    lobby.pending_matches[0].started_at = earlier;
+    drop(lobby);
+    assert_eq!(
+        crate::lobby::record_abandoned_matches(&state)
+            .await
+            .unwrap(),
+        1
+    );
+
+    let lobby = state.lock().await;
+    assert!(lobby.pending_matches.is_empty());
+    let records = lobby.matches.query(&MatchQuery::default()).unwrap();
+    assert_eq!(records[0].outcome, Outcome::Abandoned);
+}
+
+#[tokio::test]
+async fn report_the_rematch_not_the_match_before() {
+    let (state, code) = state_with_match(&["a", "b"]).await;
+    let token = state
+        .lock()
+        .await
+        .config
+        .report_tokens
+        .issue("a", &code, SystemTime::now());
+    let report = |outcome: &str| {
+        let (state, token) = (state.clone(), token.clone());
+        let body = serde_json::json!({ "reportToken": token, "outcome": outcome });
+        async move {
+            send_request(
+                matches_router(),
+                &state,
+                "POST",
+                "/matches",
+                None,
+                Some(body),
+            )
+            .await
+        }
+    };
+
+    assert_eq!(report("Won").await.0, StatusCode::CREATED);
+    // same room, same clients, a new match with its own id
+    state.lock().await.track_match(&code, SystemTime::now());
+    assert_eq!(report("Lost").await.0, StatusCode::CREATED);
+
+    let lobby = state.lock().await;
+    let records = lobby.matches.query(&MatchQuery::default()).unwrap();
+    assert_eq!(
+        records.iter().map(|r| r.outcome).collect::<Vec<_>>(),
+        [Outcome::Lost, Outcome::Won]
+    );
+}
@@ -0,0 +1,12 @@
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::lobby::SharedState;
+
+/// Prometheus scrape endpoint.
+pub async fn metrics_handler(State(state): State<SharedState>) -> impl IntoResponse {
+    let lobby = state.lock().await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        lobby.metrics.render(&lobby),
+    )
+}
@@ -1,3 +1,6 @@
+pub mod admin;
 pub mod health;
+pub mod matches;
+pub mod metrics;
+pub mod rooms;
 pub mod websocket;
-
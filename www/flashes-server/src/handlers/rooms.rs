@@ -0,0 +1,314 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use std::time::Instant;
+
+use crate::lobby::{RoomSummary, SharedState};
+use crate::maps::{GameMap, MapSummary};
+
+type RoomResponse = Result<Json<RoomSummary>, (StatusCode, &'static str)>;
+
+/// Either a map from the catalog or a custom one, the default map when neither is given
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateRoom {
+    /// name of a map from `GET /maps`
+    #[serde(default)]
+    pub map: Option<String>,
+    #[serde(default)]
+    pub custom: Option<CustomMap>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CustomMap {
+    /// rows of glyphs separated by newlines
+    pub rows: String,
+    #[serde(default, rename = "viewWidth")]
+    pub view_width: Option<u8>,
+}
+
+/// Lobby endpoints, clients then connect to `/ws` with the room code.
+pub fn rooms_router() -> Router<SharedState> {
+    Router::new()
+        .route("/rooms", get(list_rooms).post(create_room))
+        .route("/rooms/quick-match", post(quick_match))
+        .route("/rooms/{code}", get(get_room))
+        .route("/rooms/{code}/join", post(join_room))
+        .route("/maps", get(list_maps))
+}
+
+/// The map catalog rooms can be created with.
+pub async fn list_maps(State(state): State<SharedState>) -> Json<Vec<MapSummary>> {
+    let lobby = state.lock().await;
+    Json(
+        lobby
+            .maps
+            .iter()
+            .map(|(name, map)| MapSummary::new(name, map))
+            .collect(),
+    )
+}
+
+/// Rooms with a free player or spectator slot, oldest first.
+pub async fn list_rooms(State(state): State<SharedState>) -> Json<Vec<RoomSummary>> {
+    let lobby = state.lock().await;
+    Json(lobby.open_rooms().map(|room| room.summary()).collect())
+}
+
+pub async fn create_room(
+    State(state): State<SharedState>,
+    Json(request): Json<CreateRoom>,
+) -> Result<(StatusCode, Json<RoomSummary>), (StatusCode, &'static str)> {
+    let mut lobby = state.lock().await;
+
+    let map = match (request.map, request.custom) {
+        (Some(_), Some(_)) => {
+            return Err((StatusCode::BAD_REQUEST, "pick a map or send a custom one"));
+        }
+        (Some(name), None) => lobby
+            .maps
+            .get(&name)
+            .cloned()
+            .ok_or((StatusCode::NOT_FOUND, "no map with that name"))?,
+        (None, Some(custom)) => {
+            let map = GameMap::from_rows(&custom.rows, custom.view_width)
+                .map_err(|reason| (StatusCode::BAD_REQUEST, reason))?;
+            map.validate()
+                .map_err(|reason| (StatusCode::BAD_REQUEST, reason))?;
+            map
+        }
+        (None, None) => lobby.default_map(),
+    };
+    let room = lobby.create_room(map, Instant::now());
+
+    Ok((StatusCode::CREATED, Json(room.summary())))
+}
+
+pub async fn get_room(State(state): State<SharedState>, Path(code): Path<String>) -> RoomResponse {
+    let lobby = state.lock().await;
+    lobby
+        .room(&code)
+        .map(|room| Json(room.summary()))
+        .ok_or((StatusCode::NOT_FOUND, "no room with that code"))
+}
+
+/// Checks the room can take another client before connecting to it.
+pub async fn join_room(State(state): State<SharedState>, Path(code): Path<String>) -> RoomResponse {
+    let lobby = state.lock().await;
+    let room = lobby
+        .room(&code)
+        .ok_or((StatusCode::NOT_FOUND, "no room with that code"))?;
+
+    match room.has_free_slots() {
+        true => Ok(Json(room.summary())),
+        false => Err((StatusCode::CONFLICT, "room is full")),
+    }
+}
+
+/// The oldest room waiting for a second peer, a new one if nobody is waiting.
+pub async fn quick_match(State(state): State<SharedState>) -> Json<RoomSummary> {
+    let mut lobby = state.lock().await;
+    Json(lobby.quick_match(Instant::now()).summary())
+}
+
+#[cfg(test)]
+use crate::test_support::{send_request, test_client};
+
+#[tokio::test]
+async fn create_and_list_rooms() {
+    let state = SharedState::default();
+
+    let (status, body) = send_request(
+        rooms_router(),
+        &state,
+        "POST",
+        "/rooms",
+        None,
+        Some(serde_json::json!({})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let created: RoomSummary = serde_json::from_value(body.unwrap()).unwrap();
+    assert_eq!(created.free_player_slots, 1);
+
+    let (status, body) = send_request(rooms_router(), &state, "GET", "/rooms", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let rooms: Vec<RoomSummary> = serde_json::from_value(body.unwrap()).unwrap();
+    assert_eq!(rooms, vec![created.clone()]);
+
+    let (status, _) = send_request(
+        rooms_router(),
+        &state,
+        "GET",
+        &format!("/rooms/{}", created.code),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn create_room_with_custom_map() {
+    let state = SharedState::default();
+
+    let (status, _) = send_request(
+        rooms_router(),
+        &state,
+        "POST",
+        "/rooms",
+        None,
+        Some(serde_json::json!({ "custom": { "rows": "P.\nGX" } })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(state.lock().await.rooms[0].session.map.level, b"P.GX");
+
+    for rows in ["..\nGX", "PT\nTX\nG.", "P.\nGX."] {
+        let (status, _) = send_request(
+            rooms_router(),
+            &state,
+            "POST",
+            "/rooms",
+            None,
+            Some(serde_json::json!({ "custom": { "rows": rows } })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", rows);
+    }
+}
+
+#[tokio::test]
+async fn create_room_from_catalog() {
+    let state = SharedState::default();
+    let open = GameMap::from_rows("P.G\n..X\n...", None).unwrap();
+    state
+        .lock()
+        .await
+        .maps
+        .insert("open".to_string(), open.clone());
+
+    let (status, body) = send_request(rooms_router(), &state, "GET", "/maps", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let maps: Vec<MapSummary> = serde_json::from_value(body.unwrap()).unwrap();
+    assert_eq!(
+        maps.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(),
+        ["default", "open"]
+    );
+
+    let (status, _) = send_request(
+        rooms_router(),
+        &state,
+        "POST",
+        "/rooms",
+        None,
+        Some(serde_json::json!({ "map": "open" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(state.lock().await.rooms[0].session.map.level, open.level);
+
+    let (status, _) = send_request(
+        rooms_router(),
+        &state,
+        "POST",
+        "/rooms",
+        None,
+        Some(serde_json::json!({ "map": "missing" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn join_room_by_code() {
+    let state = SharedState::default();
+    let code = {
+        let mut lobby = state.lock().await;
+        let room = lobby.create_room(crate::maps::get_default_map(), Instant::now());
+        room.session.join(test_client("a"), Instant::now());
+        room.code.clone()
+    };
+
+    let (status, _) = send_request(
+        rooms_router(),
+        &state,
+        "POST",
+        &format!("/rooms/{}/join", code),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send_request(
+        rooms_router(),
+        &state,
+        "POST",
+        "/rooms/NOROOM/join",
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    {
+        let mut lobby = state.lock().await;
+        lobby
+            .room_mut(&code)
+            .unwrap()
+            .session
+            .join(test_client("b"), Instant::now());
+    }
+    let (status, _) = send_request(
+        rooms_router(),
+        &state,
+        "POST",
+        &format!("/rooms/{}/join", code),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn quick_match_into_waiting_room() {
+    let state = SharedState::default();
+    let (_, body) = send_request(
+        rooms_router(),
+        &state,
+        "POST",
+        "/rooms/quick-match",
+        None,
+        None,
+    )
+    .await;
+    let first: RoomSummary = serde_json::from_value(body.unwrap()).unwrap();
+
+    {
+        let mut lobby = state.lock().await;
+        lobby
+            .room_mut(&first.code)
+            .unwrap()
+            .session
+            .join(test_client("a"), Instant::now());
+    }
+
+    let (status, body) = send_request(
+        rooms_router(),
+        &state,
+        "POST",
+        "/rooms/quick-match",
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let matched: RoomSummary = serde_json::from_value(body.unwrap()).unwrap();
+    assert_eq!(matched.code, first.code);
+    assert_eq!(matched.free_player_slots, 0);
+}
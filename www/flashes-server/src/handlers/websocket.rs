@@ -3,72 +3,221 @@ use axum::{
         ws::{Message, WebSocket},
         State, WebSocketUpgrade,
     },
-    response::Response,
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
 use futures_util::{SinkExt, StreamExt};
-use tokio::sync::mpsc;
+use std::time::{Instant, SystemTime};
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
 use uuid::Uuid;
 
-use crate::game::{Client, Role, SharedState};
-use crate::messages::{ClientMessage, ServerMessage};
+use crate::game::{Client, ClientSender, GameSession, Role};
+use crate::limits::RateLimiter;
+use crate::lobby::{record_abandoned_matches, SharedState};
+use crate::messages::{
+    check_protocol_version, negotiate_features, ClientMessage, Encoding, ErrorCode, Feature,
+    ProtocolError, ServerMessage, PROTOCOL_VERSION,
+};
 
 pub async fn websocket_handler(ws: WebSocketUpgrade, State(state): State<SharedState>) -> Response {
-    ws.on_upgrade(|socket| handle_websocket(socket, state))
+    let max_message_bytes = {
+        let lobby = state.lock().await;
+        if lobby.shutting_down {
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+        lobby.config.max_message_bytes
+    };
+    ws.max_message_size(max_message_bytes)
+        .max_frame_size(max_message_bytes)
+        .on_upgrade(|socket| handle_websocket(socket, state))
 }
 
+/// Per-connection state, the id changes when a client resumes and the room once it joins
+struct Connection {
+    client_id: String,
+    connected_at: Instant,
+    room: Option<String>,
+    sender: ClientSender,
+    encoding: watch::Sender<Encoding>,
+}
+
+/// Serves one connection inside a span that picks up the room and role once it joins.
 pub async fn handle_websocket(websocket: WebSocket, state: SharedState) {
-    let (mut ws_sender, mut ws_receiver) = websocket.split();
-    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
     let client_id = Uuid::new_v4().to_string();
+    let span = info_span!(
+        "connection",
+        client_id = %client_id,
+        room = field::Empty,
+        role = field::Empty
+    );
 
-    // handle outgoing messages
-    let ws_sender_task = tokio::spawn(async move {
-        while let Some(message) = rx.recv().await {
-            if ws_sender.send(Message::Text(message.into())).await.is_err() {
-                break;
+    serve_connection(websocket, client_id, state)
+        .instrument(span)
+        .await
+}
+
+async fn serve_connection(websocket: WebSocket, client_id: String, state: SharedState) {
+    let (mut ws_sender, mut ws_receiver) = websocket.split();
+    // JSON until the client asks for something else
+    let (encoding_tx, encoding_rx) = watch::channel(Encoding::Json);
+    let (config, metrics) = {
+        let lobby = state.lock().await;
+        (lobby.config.clone(), lobby.metrics.clone())
+    };
+    let connected_at = Instant::now();
+    metrics.connection_opened();
+    info!("connected");
+    let (tx, mut rx) = ClientSender::new(config.outbound_queue);
+    let mut rate_limiter = RateLimiter::new(
+        config.messages_per_second,
+        config.message_burst,
+        Instant::now(),
+    );
+    let mut connection = Connection {
+        client_id,
+        connected_at,
+        room: None,
+        sender: tx,
+        encoding: encoding_tx,
+    };
+
+    // handle outgoing messages, pinging the client in between
+    let sender_metrics = metrics.clone();
+    let ws_sender_task = tokio::spawn(
+        async move {
+            let mut heartbeat = tokio::time::interval(config.heartbeat_interval);
+            heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                let message = tokio::select! {
+                    message = rx.recv() => match message {
+                        Some(message) => {
+                            sender_metrics.message_sent(message.kind());
+                            match encode_message(&message, *encoding_rx.borrow()) {
+                                Some(message) => message,
+                                None => continue,
+                            }
+                        }
+                        None => break,
+                    },
+                    _ = heartbeat.tick() => Message::Ping(Default::default()),
+                };
+
+                if ws_sender.send(message).await.is_err() {
+                    break;
+                }
             }
         }
-    });
+        .instrument(Span::current()),
+    );
 
-    // Handle incoming messages
-    while let Some(message) = ws_receiver.next().await {
-        match message {
-            Ok(Message::Text(text)) => {
-                if let Err(e) = handle_text_message(&text, &client_id, &tx, &state).await {
-                    eprintln!("Error handling message: {}", e);
-                }
+    // Handle incoming messages, any frame (pongs included) counts as a sign of life
+    loop {
+        let message = tokio::select! {
+            message = tokio::time::timeout(config.idle_timeout, ws_receiver.next()) => message,
+            _ = connection.sender.closed() => {
+                warn!("closed by server, kicked or outbound queue full");
+                break;
             }
+        };
+        let Ok(message) = message else {
+            info!("idle timeout");
+            break;
+        };
+        let Some(message) = message else {
+            break;
+        };
+
+        let is_data = matches!(message, Ok(Message::Text(_) | Message::Binary(_)));
+        if is_data && !rate_limiter.try_acquire(Instant::now()) {
+            debug!("rate limited");
+            send_message(
+                &connection.sender,
+                &ProtocolError::new(ErrorCode::RateLimited, "slow down").into(),
+            );
+            continue;
+        }
+
+        let client_msg: Result<ClientMessage, _> = match message {
+            Ok(Message::Text(text)) => serde_json::from_str(&text)
+                .map_err(|e| ProtocolError::new(ErrorCode::InvalidMessage, e.to_string())),
+            Ok(Message::Binary(bytes)) => rmp_serde::from_slice(&bytes)
+                .map_err(|e| ProtocolError::new(ErrorCode::InvalidMessage, e.to_string())),
             Ok(Message::Close(_)) => {
-                println!("Closing");
+                info!("closed by client");
                 break;
             }
             Err(e) => {
-                eprintln!("WebSocket error: {}", e);
+                warn!(error = %e, "websocket error");
                 break;
             }
             _ => {
                 // Ignore other message types
+                continue;
+            }
+        };
+
+        let result = match client_msg {
+            Ok(client_msg) => {
+                metrics.message_received(client_msg.kind());
+                handle_client_message(client_msg, &mut connection, &state).await
+            }
+            Err(e) => {
+                metrics.message_received("Invalid");
+                Err(e)
             }
+        };
+        if let Err(e) = result {
+            warn!(code = ?e.code, "refused message: {}", e.message);
+            send_message(&connection.sender, &e.into());
         }
     }
 
-    cleanup_client(&client_id, &state).await;
+    cleanup_client(&connection, &state).await;
     ws_sender_task.abort();
+    metrics.connection_closed(connected_at.elapsed());
+    info!(
+        duration_secs = connected_at.elapsed().as_secs(),
+        "disconnected"
+    );
 }
 
-async fn handle_text_message(
-    text: &str,
-    client_id: &str,
-    sender: &mpsc::UnboundedSender<String>,
+async fn handle_client_message(
+    client_msg: ClientMessage,
+    connection: &mut Connection,
     state: &SharedState,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let client_msg: ClientMessage = serde_json::from_str(text)?;
-
+) -> Result<(), ProtocolError> {
     match client_msg {
-        ClientMessage::ClientJoined => {
-            respond_with_role(client_id, sender, state).await;
-            // Check if we have both player and spectator - if so, send peer IDs
-            register_peer(state).await;
+        ClientMessage::ClientJoined {
+            protocol_version,
+            features,
+            room,
+            resume_token,
+        } => {
+            check_protocol_version(protocol_version)?;
+            let features = negotiate_features(&features);
+            // the acknowledgement already goes out in the new encoding
+            connection
+                .encoding
+                .send_replace(Encoding::negotiate(&features));
+            let role = respond_with_role(
+                connection,
+                features,
+                room.as_deref(),
+                resume_token.as_deref(),
+                state,
+            )
+            .await?;
+            // let everyone in the room know about each other
+            register_peer(connection, role, state).await;
+        }
+        ClientMessage::Unknown => {
+            return Err(ProtocolError::new(
+                ErrorCode::UnknownMessageType,
+                "unknown message type",
+            ));
         }
     }
 
@@ -76,83 +225,833 @@ async fn handle_text_message(
 }
 
 async fn respond_with_role(
-    client_id: &str,
-    sender: &mpsc::UnboundedSender<String>,
+    connection: &mut Connection,
+    features: Vec<Feature>,
+    room: Option<&str>,
+    resume_token: Option<&str>,
     state: &SharedState,
-) {
-    let mut session = state.lock().await;
-
-    println!("client joined {}", client_id);
-    let role = if session.player.is_none() {
-        println!("assigned player");
-        session.player = Some(Client {
-            id: client_id.to_string(),
-            sender: sender.clone(),
-        });
-        Role::Player
-    } else if session.spectator.is_none() {
-        println!("assigned spectator");
-        session.spectator = Some(Client {
-            id: client_id.to_string(),
-            sender: sender.clone(),
-        });
-        Role::Spectator
-    } else {
-        println!("session full");
-        return; // Session full
+) -> Result<Role, ProtocolError> {
+    let mut lobby = state.lock().await;
+    let now = Instant::now();
+
+    if connection.room.is_some() {
+        return Err(ProtocolError::new(
+            ErrorCode::AlreadyJoined,
+            "this connection already joined a room",
+        ));
+    }
+
+    let mut room_code = room.map(str::to_string);
+    let claims =
+        resume_token.and_then(|token| lobby.config.resume_tokens.verify(token, SystemTime::now()));
+    if let Some(claims) = claims {
+        // a live connection already owns this identity
+        if lobby.is_connected(&claims.client_id) {
+            info!(resumed = %claims.client_id, "resume rejected, still connected");
+        } else {
+            info!(resumed = %claims.client_id, "resumed identity");
+            Span::current().record("client_id", field::display(&claims.client_id));
+            connection.client_id = claims.client_id;
+            // back to the room the token was issued for, if it's still around
+            if lobby.room(&claims.room).is_some() {
+                room_code = Some(claims.room);
+            }
+        }
+    }
+
+    let room_code = match room_code {
+        Some(code) => code,
+        None => lobby.quick_match(now).code.clone(),
+    };
+    let resume_token = features.contains(&Feature::ResumeToken).then(|| {
+        lobby
+            .config
+            .resume_tokens
+            .issue(&connection.client_id, &room_code, SystemTime::now())
+    });
+    let report_token =
+        lobby
+            .config
+            .report_tokens
+            .issue(&connection.client_id, &room_code, SystemTime::now());
+    let Some(room) = lobby.room_mut(&room_code) else {
+        return Err(ProtocolError::new(
+            ErrorCode::RoomNotFound,
+            format!("no room with code {}", room_code),
+        ));
+    };
+
+    let client = Client {
+        id: connection.client_id.clone(),
+        sender: connection.sender.clone(),
+        features: features.clone(),
+        connected_at: connection.connected_at,
+    };
+    let Some((role, slot)) = room.session.join(client, now) else {
+        return Err(ProtocolError::new(
+            ErrorCode::SessionFull,
+            "no player or spectator slots left",
+        ));
     };
+    Span::current().record("room", field::display(&room_code));
+    Span::current().record("role", field::debug(role));
+    info!(slot, "joined room");
+    connection.room = Some(room_code.clone());
 
     let response = ServerMessage::ClientAcknowledged {
         role,
-        map: session.map.clone(),
-        client_id: client_id.to_string(),
+        map: room.session.map.clone(),
+        client_id: connection.client_id.clone(),
+        room: room_code.clone(),
+        slot,
+        protocol_version: PROTOCOL_VERSION,
+        features,
+        resume_token,
+        report_token,
     };
 
     // Send response immediately
-    if let Ok(json) = serde_json::to_string(&response) {
-        let _ = sender.send(json);
-    }
+    send_message(&connection.sender, &response);
+    lobby.track_match(&room_code, SystemTime::now());
+
+    Ok(role)
 }
 
-async fn cleanup_client(client_id: &str, state: &SharedState) {
-    let mut session = state.lock().await;
+async fn cleanup_client(connection: &Connection, state: &SharedState) {
+    let Some(room_code) = connection.room.clone() else {
+        return;
+    };
+    if let Err(e) = record_abandoned_matches(state).await {
+        warn!(error = %e, "couldn't record abandoned matches");
+    }
+    let mut lobby = state.lock().await;
+    let Some(room) = lobby.room_mut(&room_code) else {
+        return;
+    };
+    let session = &mut room.session;
 
-    // Remove from player slot
-    if let Some(ref player) = session.player {
-        if player.id == client_id {
-            println!("dropping player");
-            session.player = None;
-        }
+    let Some(role) = session.leave(&connection.client_id, Instant::now()) else {
+        return;
+    };
+    info!(?role, "left room");
+    announce_departure(session, &connection.client_id);
+
+    // give the client a chance to come back before their slot is up for grabs
+    let reconnect_grace = session.config.reconnect_grace;
+    if !reconnect_grace.is_zero() {
+        let state = state.clone();
+        let span = info_span!("room", code = %room_code);
+        tokio::spawn(
+            async move {
+                tokio::time::sleep(reconnect_grace).await;
+
+                let mut lobby = state.lock().await;
+                if let Some(room) = lobby.room_mut(&room_code) {
+                    if room.session.expire_reservations(Instant::now()) > 0 {
+                        info!("reservation expired");
+                        promote_spectator(&mut room.session);
+                    }
+                }
+                lobby.prune_empty_rooms(Instant::now());
+            }
+            .instrument(span),
+        );
+    } else {
+        lobby.prune_empty_rooms(Instant::now());
     }
+}
+
+/// Tells the room a client is gone and lets a spectator take their place.
+pub(crate) fn announce_departure(session: &mut GameSession, client_id: &str) {
+    let message = ServerMessage::PeerLeft {
+        peer_id: client_id.to_string(),
+    };
+    for (_, client) in session.clients() {
+        send_message(&client.sender, &message);
+    }
+
+    promote_spectator(session);
+}
 
-    // Remove from spectator slot
-    if let Some(ref spectator) = session.spectator {
-        if spectator.id == client_id {
-            println!("dropping spectator");
-            session.spectator = None;
+fn promote_spectator(session: &mut GameSession) {
+    if let Some(promotion) = session.promote_spectator() {
+        info!(client_id = %promotion.client.id, slot = promotion.slot, "promoted spectator");
+        send_message(
+            &promotion.client.sender,
+            &ServerMessage::RoleChanged {
+                role: Role::Player,
+                slot: promotion.slot,
+            },
+        );
+
+        // peers check whose moves an update carries by slot
+        for (_, peer) in session
+            .clients()
+            .filter(|(_, c)| c.id != promotion.client.id && c.supports(Feature::RoleChange))
+        {
+            send_message(
+                &peer.sender,
+                &ServerMessage::PeerRoleChanged {
+                    peer_id: promotion.client.id.clone(),
+                    role: Role::Player,
+                    slot: promotion.slot,
+                },
+            );
         }
     }
 }
 
-async fn register_peer(state: &SharedState) {
-    let session = state.lock().await;
+/// Introduces the new client to everyone already in the room, and vice versa.
+async fn register_peer(connection: &Connection, role: Role, state: &SharedState) {
+    let lobby = state.lock().await;
+    let Some(room) = connection.room.as_deref().and_then(|code| lobby.room(code)) else {
+        return;
+    };
+    let session = &room.session;
+    let client_id = &connection.client_id;
+
+    let Some((_, new_client)) = session.clients().find(|(_, c)| c.id == *client_id) else {
+        return;
+    };
+    let Some((_, slot)) = session.slot_of(client_id) else {
+        return;
+    };
 
-    if let (Some(ref player), Some(ref spectator)) = (&session.player, &session.spectator) {
-        // Send peer IDs to both clients
-        let player_message = ServerMessage::PeerJoined {
-            peer_id: spectator.id.clone(),
+    for (peer_role, peer) in session.clients().filter(|(_, c)| c.id != *client_id) {
+        let Some((_, peer_slot)) = session.slot_of(&peer.id) else {
+            continue;
         };
-        let spectator_message = ServerMessage::PeerJoined {
-            peer_id: player.id.clone(),
+        send_message(
+            &peer.sender,
+            &ServerMessage::PeerJoined {
+                peer_id: client_id.to_string(),
+                role,
+                slot,
+            },
+        );
+        send_message(
+            &new_client.sender,
+            &ServerMessage::PeerJoined {
+                peer_id: peer.id.clone(),
+                role: peer_role,
+                slot: peer_slot,
+            },
+        );
+    }
+}
+
+fn send_message(sender: &ClientSender, message: &ServerMessage) {
+    sender.send(message.clone());
+}
+
+fn encode_message(message: &ServerMessage, encoding: Encoding) -> Option<Message> {
+    match encoding {
+        Encoding::Json => serde_json::to_string(message)
+            .ok()
+            .map(|json| Message::Text(json.into())),
+        Encoding::MessagePack => rmp_serde::to_vec_named(message)
+            .ok()
+            .map(|bytes| Message::Binary(bytes.into())),
+    }
+}
+
+#[cfg(test)]
+async fn spawn_test_server(
+    session: crate::game::SessionConfig,
+) -> (std::net::SocketAddr, SharedState) {
+    spawn_test_server_with_origins(session, &[]).await
+}
+
+#[cfg(test)]
+async fn spawn_test_server_with_origins(
+    session: crate::game::SessionConfig,
+    allowed_origins: &[&str],
+) -> (std::net::SocketAddr, SharedState) {
+    let config = crate::config::ServerConfig {
+        bind_address: "127.0.0.1:0".parse().unwrap(),
+        allowed_origins: crate::origins::OriginAllowlist::parse(allowed_origins).unwrap(),
+        map_dir: None,
+        match_log: None,
+        admin_token: None,
+        session,
+    };
+    let state = crate::build_state(&config).unwrap();
+    let router = crate::build_router(state.clone(), &config);
+
+    let listener = tokio::net::TcpListener::bind(config.bind_address)
+        .await
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    (addr, state)
+}
+
+#[cfg(test)]
+async fn connected_clients(state: &SharedState) -> usize {
+    let lobby = state.lock().await;
+    lobby
+        .rooms
+        .iter()
+        .map(|room| room.session.clients().count())
+        .sum()
+}
+
+#[cfg(test)]
+fn heartbeat_test_config() -> crate::game::SessionConfig {
+    use std::time::Duration;
+
+    crate::game::SessionConfig {
+        reconnect_grace: Duration::ZERO,
+        heartbeat_interval: Duration::from_millis(20),
+        idle_timeout: Duration::from_millis(100),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn drop_stalled_client_after_idle_timeout() {
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, state) = spawn_test_server(heartbeat_test_config()).await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+    client
+        .send(tungstenite::Message::text(
+            r#"{"type":"ClientJoined","protocolVersion":1}"#,
+        ))
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(connected_clients(&state).await, 1);
+
+    // never read, so pings go unanswered
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(connected_clients(&state).await, 0);
+}
+
+#[tokio::test]
+async fn keep_client_that_answers_pings() {
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, state) = spawn_test_server(heartbeat_test_config()).await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+    client
+        .send(tungstenite::Message::text(
+            r#"{"type":"ClientJoined","protocolVersion":1}"#,
+        ))
+        .await
+        .unwrap();
+
+    // reading lets tungstenite answer the pings
+    let reader = tokio::spawn(async move { while client.next().await.is_some() {} });
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(connected_clients(&state).await, 1);
+    reader.abort();
+}
+
+#[cfg(test)]
+async fn next_server_message<S>(client: &mut S) -> ServerMessage
+where
+    S: futures_util::Stream<
+            Item = Result<
+                tokio_tungstenite::tungstenite::Message,
+                tokio_tungstenite::tungstenite::Error,
+            >,
+        > + Unpin,
+{
+    loop {
+        let decoded = match client.next().await.unwrap().unwrap() {
+            tokio_tungstenite::tungstenite::Message::Text(text) => serde_json::from_str(&text).ok(),
+            tokio_tungstenite::tungstenite::Message::Binary(bytes) => {
+                rmp_serde::from_slice(&bytes).ok()
+            }
+            _ => None,
         };
+        if let Some(message) = decoded {
+            return message;
+        }
+    }
+}
+
+#[tokio::test]
+async fn reply_with_error_to_bad_messages() {
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, _) = spawn_test_server(Default::default()).await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+
+    for (text, expected_code) in [
+        ("not json", ErrorCode::InvalidMessage),
+        (r#"{"type":"Teleport"}"#, ErrorCode::UnknownMessageType),
+        // cached client from before protocol versions
+        (r#"{"type":"ClientJoined"}"#, ErrorCode::IncompatibleVersion),
+        (
+            r#"{"type":"ClientJoined","protocolVersion":1,"room":"NOROOM"}"#,
+            ErrorCode::RoomNotFound,
+        ),
+    ] {
+        client.send(tungstenite::Message::text(text)).await.unwrap();
+        assert!(matches!(
+            next_server_message(&mut client).await,
+            ServerMessage::Error { code, .. } if code == expected_code
+        ));
+    }
+
+    let join = r#"{"type":"ClientJoined","protocolVersion":1}"#;
+    client.send(tungstenite::Message::text(join)).await.unwrap();
+    assert!(matches!(
+        next_server_message(&mut client).await,
+        ServerMessage::ClientAcknowledged { .. }
+    ));
+    client.send(tungstenite::Message::text(join)).await.unwrap();
+    assert!(matches!(
+        next_server_message(&mut client).await,
+        ServerMessage::Error {
+            code: ErrorCode::AlreadyJoined,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn reply_with_error_when_session_is_full() {
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, state) = spawn_test_server(Default::default()).await;
+    let (code, slots) = {
+        let mut lobby = state.lock().await;
+        let room = lobby.create_room(crate::maps::get_default_map(), Instant::now());
+        (
+            room.code.clone(),
+            room.session.players.len() + room.session.spectators.len(),
+        )
+    };
+    let join = format!(
+        r#"{{"type":"ClientJoined","protocolVersion":1,"room":"{}"}}"#,
+        code
+    );
+
+    // wait for each acknowledgement so joins are handled in order
+    let mut clients = Vec::new();
+    for i in 0..=slots {
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+            .await
+            .unwrap();
+        client
+            .send(tungstenite::Message::text(join.clone()))
+            .await
+            .unwrap();
+
+        let reply = next_server_message(&mut client).await;
+        if i < slots {
+            assert!(matches!(reply, ServerMessage::ClientAcknowledged { .. }));
+            clients.push(client);
+            continue;
+        }
+        assert!(matches!(
+            reply,
+            ServerMessage::Error {
+                code: ErrorCode::SessionFull,
+                ..
+            }
+        ));
+    }
+}
+
+#[tokio::test]
+async fn switch_to_message_pack_when_negotiated() {
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, _) = spawn_test_server(Default::default()).await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+
+    let join = rmp_serde::to_vec_named(&serde_json::json!({
+        "type": "ClientJoined",
+        "protocolVersion": 1,
+        "features": ["messagePack"],
+    }))
+    .unwrap();
+    client
+        .send(tungstenite::Message::binary(join))
+        .await
+        .unwrap();
+
+    let bytes = loop {
+        match client.next().await.unwrap().unwrap() {
+            tungstenite::Message::Binary(bytes) => break bytes,
+            tungstenite::Message::Text(_) => panic!("expected a binary frame"),
+            // heartbeat
+            _ => continue,
+        }
+    };
+    let acknowledged: ServerMessage = rmp_serde::from_slice(&bytes).unwrap();
+    assert!(matches!(
+        acknowledged,
+        ServerMessage::ClientAcknowledged { ref features, .. } if features.contains(&Feature::MessagePack)
+    ));
+}
+
+#[tokio::test]
+async fn resume_identity_and_room_with_token() {
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, _) = spawn_test_server(Default::default()).await;
+    let join = r#"{"type":"ClientJoined","protocolVersion":1,"features":["resumeToken"]}"#;
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+    client.send(tungstenite::Message::text(join)).await.unwrap();
+    let ServerMessage::ClientAcknowledged {
+        client_id,
+        room,
+        slot,
+        resume_token: Some(resume_token),
+        ..
+    } = next_server_message(&mut client).await
+    else {
+        panic!("expected an acknowledgement with a resume token");
+    };
+    client.close(None).await.unwrap();
+    while client.next().await.is_some() {}
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+    let rejoin = serde_json::json!({
+        "type": "ClientJoined",
+        "protocolVersion": 1,
+        "features": ["resumeToken"],
+        "resumeToken": resume_token,
+    });
+    client
+        .send(tungstenite::Message::text(rejoin.to_string()))
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        next_server_message(&mut client).await,
+        ServerMessage::ClientAcknowledged {
+            client_id: ref resumed_id,
+            room: ref resumed_room,
+            role: Role::Player,
+            slot: resumed_slot,
+            ..
+        } if *resumed_id == client_id && *resumed_room == room && resumed_slot == slot
+    ));
+}
+
+#[tokio::test]
+async fn refuse_upgrades_from_other_origins() {
+    use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+
+    let (addr, _) =
+        spawn_test_server_with_origins(Default::default(), &["https://example.com"]).await;
+    let upgrade_from = |origin: &str| {
+        let mut request = format!("ws://{}/ws", addr).into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("origin", origin.parse().unwrap());
+        request
+    };
 
-        if let Ok(json) = serde_json::to_string(&player_message) {
-            let _ = player.sender.send(json);
+    for origin in ["https://evilexample.com", "https://example.com.evil.com"] {
+        let result = tokio_tungstenite::connect_async(upgrade_from(origin)).await;
+        assert!(matches!(
+            result,
+            Err(tungstenite::Error::Http(ref response)) if response.status() == 403
+        ));
+    }
+
+    assert!(
+        tokio_tungstenite::connect_async(upgrade_from("https://example.com"))
+            .await
+            .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn refuse_messages_past_the_rate_limit() {
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, _) = spawn_test_server(crate::game::SessionConfig {
+        messages_per_second: 1,
+        message_burst: 2,
+        ..Default::default()
+    })
+    .await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+
+    for _ in 0..5 {
+        client
+            .send(tungstenite::Message::text(r#"{"type":"Flood"}"#))
+            .await
+            .unwrap();
+    }
+
+    let mut codes = Vec::new();
+    for _ in 0..5 {
+        if let ServerMessage::Error { code, .. } = next_server_message(&mut client).await {
+            codes.push(code);
         }
-        if let Ok(json) = serde_json::to_string(&spectator_message) {
-            let _ = spectator.sender.send(json);
+    }
+    assert_eq!(
+        codes,
+        [
+            ErrorCode::UnknownMessageType,
+            ErrorCode::UnknownMessageType,
+            ErrorCode::RateLimited,
+            ErrorCode::RateLimited,
+            ErrorCode::RateLimited,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn close_connections_sending_oversized_frames() {
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, _) = spawn_test_server(crate::game::SessionConfig {
+        max_message_bytes: 1024,
+        ..Default::default()
+    })
+    .await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+
+    client
+        .send(tungstenite::Message::text("x".repeat(4096)))
+        .await
+        .unwrap();
+
+    let closed = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            match client.next().await {
+                Some(Ok(tungstenite::Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            }
+        }
+    })
+    .await;
+    assert!(closed.is_ok());
+}
+
+#[tokio::test]
+async fn drop_clients_that_fall_behind() {
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, state) = spawn_test_server(crate::game::SessionConfig {
+        reconnect_grace: Duration::ZERO,
+        outbound_queue: 4,
+        ..Default::default()
+    })
+    .await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+    client
+        .send(tungstenite::Message::text(
+            r#"{"type":"ClientJoined","protocolVersion":1}"#,
+        ))
+        .await
+        .unwrap();
+    next_server_message(&mut client).await;
+
+    {
+        // the writer can't run while the lock is held, so the queue fills up
+        let lobby = state.lock().await;
+        let sender = &lobby.rooms[0].session.clients().next().unwrap().1.sender;
+        let delivered = (0..8)
+            .filter(|_| {
+                sender.send(ServerMessage::PeerLeft {
+                    peer_id: "flood".to_string(),
+                })
+            })
+            .count();
+        assert_eq!(delivered, 4);
+    }
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(connected_clients(&state).await, 0);
+}
+
+#[tokio::test]
+async fn count_connections_and_messages() {
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, state) = spawn_test_server(Default::default()).await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+    client
+        .send(tungstenite::Message::text(
+            r#"{"type":"ClientJoined","protocolVersion":1}"#,
+        ))
+        .await
+        .unwrap();
+    next_server_message(&mut client).await;
+
+    let lobby = state.lock().await;
+    let rendered = lobby.metrics.render(&lobby);
+    for line in [
+        "flashes_connections 1",
+        "flashes_clients{role=\"Player\"} 1",
+        "flashes_rooms 1",
+        "flashes_rooms_waiting 1",
+        "flashes_messages_received_total{type=\"ClientJoined\"} 1",
+        "flashes_messages_sent_total{type=\"ClientAcknowledged\"} 1",
+    ] {
+        assert!(rendered.lines().any(|l| l == line), "missing {}", line);
+    }
+}
+
+#[tokio::test]
+async fn warn_clients_and_refuse_upgrades_when_shutting_down() {
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, state) = spawn_test_server(crate::game::SessionConfig {
+        shutdown_reconnect_after: Duration::from_millis(1500),
+        ..Default::default()
+    })
+    .await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+    client
+        .send(tungstenite::Message::text(
+            r#"{"type":"ClientJoined","protocolVersion":1}"#,
+        ))
+        .await
+        .unwrap();
+    next_server_message(&mut client).await;
+
+    assert!(crate::shutdown::drain(&state).await);
+    assert!(matches!(
+        next_server_message(&mut client).await,
+        ServerMessage::ServerShuttingDown {
+            reconnect_after_ms: 1500
         }
+    ));
+
+    let result = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await;
+    assert!(matches!(
+        result,
+        Err(tungstenite::Error::Http(ref response)) if response.status() == 503
+    ));
+}
+
+#[tokio::test]
+async fn refuse_upgrades_once_the_shutdown_signal_fires() {
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite;
+
+    let config = crate::config::ServerConfig {
+        bind_address: "127.0.0.1:0".parse().unwrap(),
+        allowed_origins: Default::default(),
+        map_dir: None,
+        match_log: None,
+        admin_token: None,
+        session: crate::game::SessionConfig {
+            shutdown_drain_timeout: Duration::from_secs(5),
+            ..Default::default()
+        },
+    };
+    let state = crate::build_state(&config).unwrap();
+    let listener = tokio::net::TcpListener::bind(config.bind_address)
+        .await
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // nobody reads this client's queue, so draining waits out the timeout
+    let (sender, mut receiver) = ClientSender::new(4);
+    {
+        let mut lobby = state.lock().await;
+        let map = lobby.default_map();
+        let room = lobby.create_room(map, Instant::now());
+        room.session.join(
+            Client {
+                sender,
+                ..crate::test_support::test_client("a")
+            },
+            Instant::now(),
+        );
+    }
+
+    let (signal, signalled) = tokio::sync::oneshot::channel::<()>();
+    let router = crate::build_router(state.clone(), &config);
+    tokio::spawn(crate::shutdown::serve_until(
+        listener,
+        router,
+        state.clone(),
+        async {
+            let _ = signalled.await;
+        },
+    ));
+
+    signal.send(()).unwrap();
+    assert!(matches!(
+        receiver.recv().await,
+        Some(ServerMessage::ServerShuttingDown { .. })
+    ));
+
+    let result = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await;
+    assert!(matches!(
+        result,
+        Err(tungstenite::Error::Http(ref response)) if response.status() == 503
+    ));
+}
+
+#[tokio::test]
+async fn send_the_rooms_map_to_every_peer() {
+    use tokio_tungstenite::tungstenite;
+
+    let (addr, state) = spawn_test_server(Default::default()).await;
+    let custom = crate::maps::GameMap::from_rows("P.G\n..X\n...", None).unwrap();
+    let code = {
+        let mut lobby = state.lock().await;
+        lobby
+            .create_room(custom.clone(), Instant::now())
+            .code
+            .clone()
+    };
+    let join = format!(
+        r#"{{"type":"ClientJoined","protocolVersion":1,"room":"{}"}}"#,
+        code
+    );
+
+    let mut clients = Vec::new();
+    for _ in 0..2 {
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+            .await
+            .unwrap();
+        client
+            .send(tungstenite::Message::text(join.clone()))
+            .await
+            .unwrap();
 
-        println!("Sent peer IDs to both clients");
+        let reply = next_server_message(&mut client).await;
+        assert!(matches!(
+            reply,
+            ServerMessage::ClientAcknowledged { ref map, .. } if map.level == custom.level
+        ));
+        clients.push(client);
     }
 }
@@ -0,0 +1,374 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::game::Role;
+use crate::maps::GameMap;
+
+/// matches nobody reported an outcome for are recorded as abandoned after this long
+pub const MATCH_REPORT_TTL: Duration = Duration::from_secs(60 * 60);
+/// how often the lobby looks for abandoned matches
+pub const MATCH_SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// records `/matches` returns when no limit is given
+pub const DEFAULT_QUERY_LIMIT: usize = 50;
+/// larger limits are cut down to this
+pub const MAX_QUERY_LIMIT: usize = 500;
+/// how much of the match log is read at a time, from the end
+const LOG_READ_BLOCK: u64 = 8 * 1024;
+
+/// How a match ended, from the players' side
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Outcome {
+    Won,
+    Lost,
+    /// nobody reported an outcome in time
+    Abandoned,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Participant {
+    pub id: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchRecord {
+    pub room: String,
+    pub map: GameMap,
+    pub participants: Vec<Participant>,
+    /// unix seconds
+    #[serde(rename = "startedAt")]
+    pub started_at: u64,
+    #[serde(rename = "endedAt")]
+    pub ended_at: u64,
+    pub outcome: Outcome,
+}
+
+/// Filters for `/matches`, newest matches come first
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MatchQuery {
+    pub room: Option<String>,
+    /// matches this client took part in
+    pub player: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl MatchQuery {
+    fn matches(&self, record: &MatchRecord) -> bool {
+        self.room.as_ref().is_none_or(|room| *room == record.room)
+            && self.player.as_ref().is_none_or(|player| {
+                record
+                    .participants
+                    .iter()
+                    .any(|participant| participant.id == *player)
+            })
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .min(MAX_QUERY_LIMIT)
+    }
+
+    /// Takes records newest first and stops reading once the limit is reached.
+    fn apply(
+        &self,
+        newest_first: impl Iterator<Item = io::Result<MatchRecord>>,
+    ) -> io::Result<Vec<MatchRecord>> {
+        newest_first
+            .filter(|record| record.as_ref().map_or(true, |record| self.matches(record)))
+            .take(self.limit())
+            .collect()
+    }
+}
+
+/// Where finished matches are kept.
+///
+/// Implementations can block, they're only called through `spawn_blocking`.
+pub trait MatchStore: fmt::Debug + Send + Sync {
+    fn record(&self, record: MatchRecord) -> io::Result<()>;
+    fn query(&self, query: &MatchQuery) -> io::Result<Vec<MatchRecord>>;
+}
+
+/// Keeps matches until the server restarts
+#[derive(Debug, Default)]
+pub struct MemoryMatchStore(Mutex<Vec<MatchRecord>>);
+
+impl MatchStore for MemoryMatchStore {
+    fn record(&self, record: MatchRecord) -> io::Result<()> {
+        self.0.lock().unwrap().push(record);
+        Ok(())
+    }
+
+    fn query(&self, query: &MatchQuery) -> io::Result<Vec<MatchRecord>> {
+        query.apply(self.0.lock().unwrap().iter().rev().cloned().map(Ok))
+    }
+}
+
+/// Appends one JSON record per line to a file
+#[derive(Debug)]
+pub struct FileMatchStore {
+    path: PathBuf,
+    /// serializes appends from different connections
+    lock: Mutex<()>,
+}
+
+impl FileMatchStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl MatchStore for FileMatchStore {
+    fn record(&self, record: MatchRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        let _guard = self.lock.lock().unwrap();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(&line)
+    }
+
+    fn query(&self, query: &MatchQuery) -> io::Result<Vec<MatchRecord>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let records = ReverseLines::new(file)?.map(|line| Ok(serde_json::from_slice(&line?)?));
+        query.apply(records)
+    }
+}
+
+/// A file's lines last to first, read a block at a time from the end.
+struct ReverseLines {
+    file: File,
+    /// everything before this offset is still to be read
+    unread: u64,
+    /// start of a line that may continue in the block before
+    partial: Vec<u8>,
+    /// complete lines in file order, handed out from the back
+    lines: Vec<Vec<u8>>,
+}
+
+impl ReverseLines {
+    fn new(file: File) -> io::Result<Self> {
+        Ok(Self {
+            unread: file.metadata()?.len(),
+            file,
+            partial: Vec::new(),
+            lines: Vec::new(),
+        })
+    }
+
+    /// Reads the block before the ones already read.
+    fn read_block(&mut self) -> io::Result<()> {
+        let len = LOG_READ_BLOCK.min(self.unread);
+        self.unread -= len;
+
+        let mut block = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(self.unread))?;
+        self.file.read_exact(&mut block)?;
+        block.append(&mut self.partial);
+
+        let mut lines = block.split(|byte| *byte == b'\n');
+        self.partial = lines.next().unwrap_or_default().to_vec();
+        self.lines = lines.map(<[u8]>::to_vec).collect();
+
+        Ok(())
+    }
+}
+
+impl Iterator for ReverseLines {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.lines.pop() {
+                match line.is_empty() {
+                    true => continue,
+                    false => return Some(Ok(line)),
+                }
+            }
+            if self.unread == 0 {
+                // the file's first line
+                return match self.partial.is_empty() {
+                    true => None,
+                    false => Some(Ok(std::mem::take(&mut self.partial))),
+                };
+            }
+            if let Err(e) = self.read_block() {
+                self.unread = 0;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Records matches on a blocking thread, call it once the lobby is unlocked.
+pub async fn record_matches(
+    store: Arc<dyn MatchStore>,
+    records: Vec<MatchRecord>,
+) -> io::Result<()> {
+    tokio::task::spawn_blocking(move || {
+        records
+            .into_iter()
+            .try_for_each(|record| store.record(record))
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// A match that started but hasn't been reported yet
+#[derive(Debug, Clone)]
+pub struct PendingMatch {
+    /// a room can go through several matches, this tells them apart
+    pub id: String,
+    pub room: String,
+    pub map: GameMap,
+    pub participants: Vec<Participant>,
+    pub started_at: SystemTime,
+}
+
+impl PendingMatch {
+    pub fn finish(self, outcome: Outcome, now: SystemTime) -> MatchRecord {
+        MatchRecord {
+            room: self.room,
+            map: self.map,
+            participants: self.participants,
+            started_at: unix_secs(self.started_at),
+            ended_at: unix_secs(now),
+            outcome,
+        }
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        now.duration_since(self.started_at)
+            .is_ok_and(|age| age >= MATCH_REPORT_TTL)
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+fn test_record(room: &str, players: &[&str]) -> MatchRecord {
+    PendingMatch {
+        id: uuid::Uuid::new_v4().to_string(),
+        room: room.to_string(),
+        map: crate::maps::get_default_map(),
+        participants: players
+            .iter()
+            .map(|id| Participant {
+                id: id.to_string(),
+                role: Role::Player,
+            })
+            .collect(),
+        started_at: UNIX_EPOCH,
+    }
+    .finish(Outcome::Won, UNIX_EPOCH + Duration::from_secs(90))
+}
+
+#[cfg(test)]
+fn assert_query(store: &dyn MatchStore) {
+    store.record(test_record("AAAAAA", &["a", "b"])).unwrap();
+    store.record(test_record("BBBBBB", &["b"])).unwrap();
+    store.record(test_record("CCCCCC", &["c"])).unwrap();
+
+    let rooms = |query: MatchQuery| {
+        store
+            .query(&query)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.room)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(rooms(MatchQuery::default()), ["CCCCCC", "BBBBBB", "AAAAAA"]);
+    assert_eq!(
+        rooms(MatchQuery {
+            player: Some("b".to_string()),
+            ..Default::default()
+        }),
+        ["BBBBBB", "AAAAAA"]
+    );
+    assert_eq!(
+        rooms(MatchQuery {
+            room: Some("AAAAAA".to_string()),
+            ..Default::default()
+        }),
+        ["AAAAAA"]
+    );
+    assert_eq!(
+        rooms(MatchQuery {
+            limit: Some(1),
+            ..Default::default()
+        }),
+        ["CCCCCC"]
+    );
+}
+
+#[test]
+fn query_matches_in_memory() {
+    assert_query(&MemoryMatchStore::default());
+}
+
+#[test]
+fn query_matches_from_file() {
+    let path = std::env::temp_dir().join(format!("flashes-matches-{}", uuid::Uuid::new_v4()));
+    let store = FileMatchStore::new(&path);
+    assert!(store.query(&MatchQuery::default()).unwrap().is_empty());
+
+    assert_query(&store);
+    let record = &store.query(&MatchQuery::default()).unwrap()[0];
+    assert_eq!(record.ended_at - record.started_at, 90);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn read_the_match_log_from_the_end() {
+    let path = std::env::temp_dir().join(format!("flashes-matches-{}", uuid::Uuid::new_v4()));
+    let store = FileMatchStore::new(&path);
+    // spans several blocks, so lines get split between reads
+    let rooms = (0..40).map(|i| format!("ROOM{:02}", i)).collect::<Vec<_>>();
+    for room in &rooms {
+        store.record(test_record(room, &["a"])).unwrap();
+    }
+    assert!(std::fs::metadata(&path).unwrap().len() > 2 * LOG_READ_BLOCK);
+
+    let newest = store
+        .query(&MatchQuery {
+            limit: Some(usize::MAX),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(
+        newest.iter().map(|r| &r.room).collect::<Vec<_>>(),
+        rooms.iter().rev().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        MatchQuery {
+            limit: Some(usize::MAX),
+            ..Default::default()
+        }
+        .limit(),
+        MAX_QUERY_LIMIT
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
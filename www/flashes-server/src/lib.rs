@@ -0,0 +1,81 @@
+pub mod config;
+pub mod game;
+pub mod handlers;
+pub mod history;
+pub mod limits;
+pub mod lobby;
+pub mod maps;
+pub mod messages;
+pub mod metrics;
+pub mod origins;
+pub mod shutdown;
+#[cfg(test)]
+mod test_support;
+pub mod tokens;
+
+use axum::{middleware, routing::get, Router};
+use std::io;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use config::ServerConfig;
+use handlers::{
+    admin::admin_router, health::health_handler, matches::matches_router, metrics::metrics_handler,
+    rooms::rooms_router, websocket::websocket_handler,
+};
+use history::FileMatchStore;
+use lobby::{spawn_match_sweep, Lobby, SharedState};
+use maps::load_map_dir;
+use origins::require_allowed_origin;
+
+/// The lobby with the configured maps loaded.
+///
+/// Has to be called from a tokio runtime, it starts sweeping abandoned matches.
+pub fn build_state(config: &ServerConfig) -> io::Result<SharedState> {
+    let mut lobby = Lobby::new(config.session.clone());
+    if let Some(map_dir) = &config.map_dir {
+        lobby.maps.extend(load_map_dir(map_dir)?);
+    }
+    if let Some(match_log) = &config.match_log {
+        lobby.matches = Arc::new(FileMatchStore::new(match_log));
+    }
+
+    let state = Arc::new(Mutex::new(lobby));
+    spawn_match_sweep(state.clone());
+
+    Ok(state)
+}
+
+/// Every route the server exposes, shared by the Shuttle and standalone entry points.
+pub fn build_router(state: SharedState, config: &ServerConfig) -> Router {
+    let allowed_origins = Arc::new(config.allowed_origins.clone());
+    let cors_origins = allowed_origins.clone();
+    let cors = CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |header, _| {
+            header
+                .to_str()
+                .is_ok_and(|origin| cors_origins.allows(origin))
+        }))
+        .allow_methods([axum::http::Method::GET, axum::http::Method::POST])
+        .allow_headers(Any);
+
+    let mut router = Router::new()
+        .route(
+            "/ws",
+            // CORS doesn't cover websocket upgrades
+            get(websocket_handler).route_layer(middleware::from_fn_with_state(
+                allowed_origins,
+                require_allowed_origin,
+            )),
+        )
+        .route("/health", get(health_handler))
+        .route("/metrics", get(metrics_handler))
+        .merge(rooms_router())
+        .merge(matches_router());
+    if let Some(token) = &config.admin_token {
+        router = router.merge(admin_router(token));
+    }
+
+    router.with_state(state).layer(cors)
+}
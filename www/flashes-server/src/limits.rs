@@ -0,0 +1,67 @@
+use std::time::Instant;
+
+/// Token bucket limiting how many messages a connection may send.
+///
+/// Holds up to `burst` tokens and refills `per_second` of them every second.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: u32, burst: u32, now: Instant) -> Self {
+        Self {
+            per_second: per_second as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            refilled_at: now,
+        }
+    }
+
+    /// Takes a token if there is one, `false` means the message should be refused.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.burst);
+        self.refilled_at = now;
+
+        match self.tokens >= 1.0 {
+            true => {
+                self.tokens -= 1.0;
+                true
+            }
+            false => false,
+        }
+    }
+}
+
+#[test]
+fn refuse_messages_past_the_burst() {
+    let now = Instant::now();
+    let mut limiter = RateLimiter::new(2, 3, now);
+
+    assert!((0..3).all(|_| limiter.try_acquire(now)));
+    assert!(!limiter.try_acquire(now));
+}
+
+#[test]
+fn refill_over_time() {
+    let now = Instant::now();
+    let mut limiter = RateLimiter::new(2, 3, now);
+    (0..3).for_each(|_| {
+        limiter.try_acquire(now);
+    });
+
+    let later = now + std::time::Duration::from_millis(500);
+    assert!(limiter.try_acquire(later));
+    assert!(!limiter.try_acquire(later));
+
+    // never more than the burst, however long the connection was quiet
+    let much_later = later + std::time::Duration::from_secs(60);
+    assert!((0..3).all(|_| limiter.try_acquire(much_later)));
+    assert!(!limiter.try_acquire(much_later));
+}
@@ -0,0 +1,407 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::game::{GameSession, SessionConfig, Slot};
+use crate::history::{
+    record_matches, MatchRecord, MatchStore, MemoryMatchStore, Outcome, Participant, PendingMatch,
+    MATCH_SWEEP_INTERVAL,
+};
+use crate::maps::{get_default_map, GameMap, DEFAULT_MAP_NAME};
+use crate::metrics::Metrics;
+
+/// how long a room sticks around after its last slot was freed
+pub const EMPTY_ROOM_TTL: Duration = Duration::from_secs(60);
+const ROOM_CODE_LENGTH: usize = 6;
+/// no 0/O or 1/I, codes get read out loud
+const ROOM_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+#[derive(Debug)]
+pub struct Room {
+    pub code: String,
+    pub created_at: Instant,
+    /// when the last slot was freed, noticed by `Lobby::prune_empty_rooms`
+    pub empty_since: Option<Instant>,
+    /// id of the pending match clients joining now are added to
+    pub current_match: Option<String>,
+    pub session: GameSession,
+}
+
+impl Room {
+    pub fn summary(&self) -> RoomSummary {
+        RoomSummary {
+            code: self.code.clone(),
+            player_slots: self.session.players.len(),
+            free_player_slots: count_free(&self.session.players),
+            spectator_slots: self.session.spectators.len(),
+            free_spectator_slots: count_free(&self.session.spectators),
+        }
+    }
+
+    pub fn has_free_slots(&self) -> bool {
+        self.session
+            .players
+            .iter()
+            .chain(self.session.spectators.iter())
+            .any(|slot| matches!(slot, Slot::Free))
+    }
+
+    /// One client in and room for another.
+    pub fn is_waiting(&self) -> bool {
+        self.session.clients().count() == 1 && self.has_free_slots()
+    }
+
+    /// Nobody connected and nobody expected back.
+    fn is_empty(&self) -> bool {
+        self.session
+            .players
+            .iter()
+            .chain(self.session.spectators.iter())
+            .all(|slot| matches!(slot, Slot::Free))
+    }
+}
+
+fn count_free(slots: &[Slot]) -> usize {
+    slots
+        .iter()
+        .filter(|slot| matches!(slot, Slot::Free))
+        .count()
+}
+
+/// What the lobby endpoints tell clients about a room
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RoomSummary {
+    pub code: String,
+    #[serde(rename = "playerSlots")]
+    pub player_slots: usize,
+    #[serde(rename = "freePlayerSlots")]
+    pub free_player_slots: usize,
+    #[serde(rename = "spectatorSlots")]
+    pub spectator_slots: usize,
+    #[serde(rename = "freeSpectatorSlots")]
+    pub free_spectator_slots: usize,
+}
+
+#[derive(Debug)]
+pub struct Lobby {
+    /// oldest first
+    pub rooms: Vec<Room>,
+    /// every new room starts from this
+    pub config: SessionConfig,
+    /// maps rooms can be created with, always has `DEFAULT_MAP_NAME`
+    pub maps: BTreeMap<String, GameMap>,
+    /// shared with connections so they can count without the lock
+    pub metrics: Arc<Metrics>,
+    /// set once the server started shutting down, new connections are turned away
+    pub shutting_down: bool,
+    /// finished matches end up here
+    pub matches: Arc<dyn MatchStore>,
+    /// started matches waiting for a player to report the outcome, kept apart from
+    /// the rooms since those are gone by the time a game ends
+    pub pending_matches: Vec<PendingMatch>,
+}
+
+impl Lobby {
+    pub fn new(config: SessionConfig) -> Self {
+        Self {
+            rooms: Vec::new(),
+            config,
+            maps: BTreeMap::from([(DEFAULT_MAP_NAME.to_string(), get_default_map())]),
+            metrics: Arc::default(),
+            shutting_down: false,
+            matches: Arc::new(MemoryMatchStore::default()),
+            pending_matches: Vec::new(),
+        }
+    }
+
+    pub fn default_map(&self) -> GameMap {
+        self.maps
+            .get(DEFAULT_MAP_NAME)
+            .cloned()
+            .unwrap_or_else(get_default_map)
+    }
+
+    pub fn create_room(&mut self, map: GameMap, now: Instant) -> &mut Room {
+        self.prune_empty_rooms(now);
+
+        let code = loop {
+            let code = generate_room_code();
+            if self.room(&code).is_none() {
+                break code;
+            }
+        };
+
+        self.rooms.push(Room {
+            code,
+            created_at: now,
+            empty_since: Some(now),
+            current_match: None,
+            session: GameSession::with_map(self.config.clone(), map),
+        });
+        self.rooms.last_mut().expect("room was just added")
+    }
+
+    pub fn room(&self, code: &str) -> Option<&Room> {
+        self.rooms.iter().find(|room| room.code == code)
+    }
+
+    pub fn room_mut(&mut self, code: &str) -> Option<&mut Room> {
+        self.rooms.iter_mut().find(|room| room.code == code)
+    }
+
+    /// Rooms someone can still join, oldest first.
+    pub fn open_rooms(&self) -> impl Iterator<Item = &Room> {
+        self.rooms.iter().filter(|room| room.has_free_slots())
+    }
+
+    /// The oldest room waiting for a second peer, or a new one on the default map.
+    pub fn quick_match(&mut self, now: Instant) -> &mut Room {
+        match self.rooms.iter().position(Room::is_waiting) {
+            Some(index) => &mut self.rooms[index],
+            None => self.create_room(self.default_map(), now),
+        }
+    }
+
+    /// Starts a match once a room has two clients, later arrivals are added to it.
+    ///
+    /// Once the room's match was reported, expired or everyone left, the next one starts fresh.
+    pub fn track_match(&mut self, code: &str, now: SystemTime) {
+        let Some(room) = self.rooms.iter_mut().find(|room| room.code == code) else {
+            return;
+        };
+        if room.session.clients().count() < 2 {
+            return;
+        }
+
+        let current = room.current_match.as_ref().and_then(|id| {
+            self.pending_matches
+                .iter()
+                .position(|pending| pending.id == *id)
+        });
+        let index = match current {
+            Some(index) => index,
+            None => {
+                let id = Uuid::new_v4().to_string();
+                room.current_match = Some(id.clone());
+                self.pending_matches.push(PendingMatch {
+                    id,
+                    room: code.to_string(),
+                    map: room.session.map.clone(),
+                    participants: Vec::new(),
+                    started_at: now,
+                });
+                self.pending_matches.len() - 1
+            }
+        };
+        let participants = &mut self.pending_matches[index].participants;
+        for (role, client) in room.session.clients() {
+            if !participants.iter().any(|p| p.id == client.id) {
+                participants.push(Participant {
+                    id: client.id.clone(),
+                    role,
+                });
+            }
+        }
+    }
+
+    /// Ends the matches nobody reported in time as abandoned.
+    ///
+    /// They're only taken out of the lobby, see `record_abandoned_matches`.
+    pub fn expire_matches(&mut self, now: SystemTime) -> Vec<MatchRecord> {
+        let (expired, pending) = std::mem::take(&mut self.pending_matches)
+            .into_iter()
+            .partition::<Vec<_>, _>(|pending| pending.is_expired(now));
+        self.pending_matches = pending;
+
+        expired
+            .into_iter()
+            .map(|pending| pending.finish(Outcome::Abandoned, now))
+            .collect()
+    }
+
+    pub fn is_connected(&self, client_id: &str) -> bool {
+        self.rooms
+            .iter()
+            .any(|room| room.session.is_connected(client_id))
+    }
+
+    /// Drops rooms that have been empty for a while, returns how many were dropped.
+    ///
+    /// Called whenever a slot is freed, so rooms that just emptied start their countdown.
+    pub fn prune_empty_rooms(&mut self, now: Instant) -> usize {
+        let before = self.rooms.len();
+        self.rooms.retain_mut(|room| match room.is_empty() {
+            true => {
+                // whoever comes next plays a new match
+                room.current_match = None;
+                now.duration_since(*room.empty_since.get_or_insert(now)) < EMPTY_ROOM_TTL
+            }
+            false => {
+                room.empty_since = None;
+                true
+            }
+        });
+
+        before - self.rooms.len()
+    }
+}
+
+impl Default for Lobby {
+    fn default() -> Self {
+        Self::new(SessionConfig::default())
+    }
+}
+
+fn generate_room_code() -> String {
+    Uuid::new_v4()
+        .as_bytes()
+        .iter()
+        .take(ROOM_CODE_LENGTH)
+        .map(|byte| ROOM_CODE_ALPHABET[*byte as usize % ROOM_CODE_ALPHABET.len()] as char)
+        .collect()
+}
+
+pub type SharedState = Arc<Mutex<Lobby>>;
+
+/// Records expired matches as abandoned, returns how many there were.
+pub async fn record_abandoned_matches(state: &SharedState) -> std::io::Result<usize> {
+    let (store, expired) = {
+        let mut lobby = state.lock().await;
+        (
+            lobby.matches.clone(),
+            lobby.expire_matches(SystemTime::now()),
+        )
+    };
+
+    let count = expired.len();
+    if count > 0 {
+        record_matches(store, expired).await?;
+    }
+
+    Ok(count)
+}
+
+/// Looks for abandoned matches every `MATCH_SWEEP_INTERVAL`, even when nobody disconnects.
+pub fn spawn_match_sweep(state: SharedState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MATCH_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = record_abandoned_matches(&state).await {
+                warn!(error = %e, "couldn't record abandoned matches");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+use crate::test_support::test_client;
+
+#[test]
+fn quick_match_into_oldest_waiting_room() {
+    let mut lobby = Lobby::default();
+    let now = Instant::now();
+
+    let first = lobby.quick_match(now).code.clone();
+    // nobody is waiting in an empty room, so a new one is made
+    let second = lobby.quick_match(now).code.clone();
+    assert_ne!(first, second);
+
+    lobby
+        .room_mut(&second)
+        .unwrap()
+        .session
+        .join(test_client("a"), now);
+    lobby
+        .room_mut(&first)
+        .unwrap()
+        .session
+        .join(test_client("b"), now);
+
+    assert_eq!(lobby.quick_match(now).code, first);
+}
+
+#[test]
+fn skip_full_rooms_when_listing() {
+    let mut lobby = Lobby::default();
+    let now = Instant::now();
+    let code = lobby.create_room(get_default_map(), now).code.clone();
+
+    let room = lobby.room_mut(&code).unwrap();
+    room.session.join(test_client("a"), now);
+    assert_eq!(room.summary().free_player_slots, 0);
+    assert_eq!(room.summary().free_spectator_slots, 1);
+    assert_eq!(lobby.open_rooms().count(), 1);
+
+    lobby
+        .room_mut(&code)
+        .unwrap()
+        .session
+        .join(test_client("b"), now);
+    assert_eq!(lobby.open_rooms().count(), 0);
+    assert!(lobby.is_connected("b"));
+}
+
+#[test]
+fn prune_rooms_left_empty() {
+    let mut lobby = Lobby::default();
+    let now = Instant::now();
+    let code = lobby.create_room(get_default_map(), now).code.clone();
+
+    assert_eq!(lobby.prune_empty_rooms(now), 0);
+
+    lobby
+        .room_mut(&code)
+        .unwrap()
+        .session
+        .join(test_client("a"), now);
+    assert_eq!(lobby.prune_empty_rooms(now + EMPTY_ROOM_TTL), 0);
+
+    assert!(lobby.is_connected("a"));
+    lobby.room_mut(&code).unwrap().session.leave("a", now);
+    // still reserved for a reconnect
+    assert_eq!(lobby.prune_empty_rooms(now + EMPTY_ROOM_TTL), 0);
+
+    let room = lobby.room_mut(&code).unwrap();
+    room.session.expire_reservations(now + EMPTY_ROOM_TTL);
+    // an old room that just emptied gets the full TTL too
+    assert_eq!(lobby.prune_empty_rooms(now + EMPTY_ROOM_TTL), 0);
+    assert_eq!(lobby.prune_empty_rooms(now + EMPTY_ROOM_TTL * 2), 1);
+    assert!(lobby.room(&code).is_none());
+}
+
+#[test]
+fn start_a_new_match_once_the_room_empties() {
+    let mut lobby = Lobby::new(SessionConfig {
+        reconnect_grace: Duration::ZERO,
+        ..SessionConfig::default()
+    });
+    let now = Instant::now();
+    let code = lobby.create_room(get_default_map(), now).code.clone();
+
+    let join = |lobby: &mut Lobby, ids: [&str; 2]| {
+        for id in ids {
+            let room = lobby.room_mut(&code).unwrap();
+            room.session.join(test_client(id), now);
+        }
+        lobby.track_match(&code, SystemTime::now());
+    };
+    join(&mut lobby, ["a", "b"]);
+    for id in ["a", "b"] {
+        lobby.room_mut(&code).unwrap().session.leave(id, now);
+    }
+    lobby.prune_empty_rooms(now);
+    join(&mut lobby, ["c", "d"]);
+
+    let participants = lobby
+        .pending_matches
+        .iter()
+        .map(|pending| pending.participants.iter().map(|p| p.id.as_str()).collect())
+        .collect::<Vec<Vec<_>>>();
+    assert_eq!(participants, [["a", "b"], ["c", "d"]]);
+    assert_ne!(lobby.pending_matches[0].id, lobby.pending_matches[1].id);
+}
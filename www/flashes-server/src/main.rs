@@ -1,37 +1,21 @@
-mod game;
-mod handlers;
-mod maps;
-mod messages;
-
-use axum::{routing::get, Router};
 use shuttle_runtime::SecretStore;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 
-use game::{GameSession, SharedState};
-use handlers::{health::health_handler, websocket::websocket_handler};
+use flashes_server::config::ServerConfig;
+use flashes_server::{build_router, build_state};
 
+/// Shuttle's runtime calls `exit` as soon as it gets SIGTERM and gives services no hook to
+/// run before it, so connections are only drained on redeploy if `POST /admin/shutdown`
+/// is called first. That route needs `ADMIN_TOKEN`, startup warns when it's missing.
 #[shuttle_runtime::main]
 async fn main(#[shuttle_runtime::Secrets] secrets: SecretStore) -> shuttle_axum::ShuttleAxum {
-    let state: SharedState = Arc::new(Mutex::new(GameSession::default()));
-
-    let allowed_origin = secrets
-        .get("ALLOWED_ORIGIN")
-        .expect("ALLOWED_ORIGIN secret must be set");
-
-    let cors = CorsLayer::new()
-        .allow_origin(AllowOrigin::predicate(move |header, _| {
-            header.as_bytes().ends_with(allowed_origin.as_bytes())
-        }))
-        .allow_methods([axum::http::Method::GET, axum::http::Method::POST])
-        .allow_headers(Any);
-
-    let router = Router::new()
-        .route("/ws", get(websocket_handler))
-        .route("/health", get(health_handler))
-        .with_state(state)
-        .layer(cors);
+    let config = ServerConfig::from_lookup(|key| secrets.get(key))
+        .map_err(|e| shuttle_runtime::Error::Custom(e.into()))?;
+    let state = build_state(&config).map_err(|e| shuttle_runtime::Error::Custom(e.into()))?;
+    if config.admin_token.is_none() {
+        tracing::warn!(
+            "ADMIN_TOKEN isn't set, redeploys will drop connections without warning clients"
+        );
+    }
 
-    Ok(router.into())
+    Ok(build_router(state, &config).into())
 }
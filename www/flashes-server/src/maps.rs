@@ -1,4 +1,13 @@
+use pathfinder::{find_path, Glyph};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+/// name of the map new rooms get when none is chosen
+pub const DEFAULT_MAP_NAME: &str = "default";
+/// cell size custom maps are drawn with
+pub const DEFAULT_CELL_WIDTH: u8 = 40;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameMap {
@@ -10,79 +19,124 @@ pub struct GameMap {
     pub view_width: u8,
 }
 
-#[derive(PartialEq, Clone, Copy)]
-pub enum Glyph {
-    Target,
-    Water,
-    Tree,
-    Rock,
-    Floor,
-    Player,
-    Monster,
-    DefeatedMonster,
-}
-
-impl Glyph {
-    /// any glyph that can be used as a pathfinding target
-    pub fn is_targetable(self) -> bool {
-        match self {
-            Glyph::Target
-            | Glyph::Floor
-            | Glyph::Player
-            | Glyph::Monster
-            | Glyph::DefeatedMonster => true,
-            _ => false,
+impl GameMap {
+    /// Builds a map from rows of glyphs separated by newlines, like `"P.\n.X"`.
+    ///
+    /// The result still needs to be validated.
+    pub fn from_rows(rows: &str, view_width: Option<u8>) -> Result<Self, &'static str> {
+        let rows: Vec<&str> = rows
+            .lines()
+            .map(str::trim)
+            .filter(|row| !row.is_empty())
+            .collect();
+        let width = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != width) {
+            return Err("every row must be the same length");
         }
+        let width = u8::try_from(width).map_err(|_| "rows can be at most 255 glyphs")?;
+
+        Ok(Self {
+            level: rows.concat().into_bytes(),
+            width,
+            cell_width: DEFAULT_CELL_WIDTH,
+            view_width: view_width.unwrap_or(width.min(DEFAULT_CAMERA_WIDTH)),
+        })
     }
-}
 
-impl From<u8> for Glyph {
-    fn from(value: u8) -> Self {
-        match value {
-            b'X' | 0 => Glyph::Target,
-            b'_' | 1 => Glyph::Water,
-            b'T' | 2 => Glyph::Tree,
-            b'*' | 3 => Glyph::Rock,
-            b'.' | 4 => Glyph::Floor,
-            b'P' | 5 => Glyph::Player,
-            b'G' | 6 => Glyph::Monster,
-            b'g' | 7 => Glyph::DefeatedMonster,
-            _ => panic!("Unexpected character {value} found for map glyph."),
+    /// Rejects maps the client can't load or nobody can win.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.width == 0 || self.view_width == 0 || self.cell_width == 0 {
+            return Err("width, viewWidth and cellWidth must be positive");
+        }
+        if self.view_width > self.width {
+            return Err("viewWidth can't be wider than the map");
+        }
+        if self.level.is_empty() || !self.level.len().is_multiple_of(self.width as usize) {
+            return Err("level must be a whole number of rows");
+        }
+        // the engine's camera assumes a square map
+        if self.level.len() / self.width as usize != self.width as usize {
+            return Err("level must have as many rows as columns");
+        }
+        if !self.level.iter().all(|glyph| GLYPHS.contains(glyph)) {
+            return Err("level contains an unknown glyph");
+        }
+        if self.player_count() == 0 {
+            return Err("level needs at least one P");
+        }
+        if self.count(Glyph::Monster) == 0 {
+            return Err("level needs at least one G");
+        }
+        if self.count(Glyph::Target) == 0 {
+            return Err("level needs at least one X");
         }
-    }
-}
 
-impl From<Glyph> for u8 {
-    fn from(value: Glyph) -> Self {
-        match value {
-            Glyph::Target => b'X',
-            Glyph::Water => b'_',
-            Glyph::Tree => b'T',
-            Glyph::Rock => b'*',
-            Glyph::Floor => b'.',
-            Glyph::Player => b'P',
-            Glyph::Monster => b'G',
-            Glyph::DefeatedMonster => b'g',
+        // same checks as the engine's `is_solvable`, for every player instead of the first
+        let glyphs = self.glyphs();
+        if find_path(&glyphs, self.width, Glyph::Monster, Glyph::Player).is_empty() {
+            return Err("G needs a path to a P");
+        }
+        let all_reach_target = (0..glyphs.len())
+            .filter(|idx| glyphs[*idx] == Glyph::Player)
+            .all(|player_idx| {
+                // the pathfinder starts from the first P, leave only this one on the map
+                let mut alone = glyphs.clone();
+                for (idx, glyph) in alone.iter_mut().enumerate() {
+                    if *glyph == Glyph::Player && idx != player_idx {
+                        *glyph = Glyph::Floor;
+                    }
+                }
+                !find_path(&alone, self.width, Glyph::Player, Glyph::Target).is_empty()
+            });
+        if !all_reach_target {
+            return Err("every P needs a path to an X");
         }
+
+        Ok(())
+    }
+
+    /// The level as engine glyphs, only call it on a level with known glyphs.
+    fn glyphs(&self) -> Vec<Glyph> {
+        self.level
+            .iter()
+            .filter_map(|glyph| Glyph::try_from(*glyph).ok())
+            .collect()
     }
+
+    /// Number of `P`s on the map, every player needs one.
+    pub fn player_count(&self) -> usize {
+        self.count(Glyph::Player)
+    }
+
+    fn count(&self, glyph: Glyph) -> usize {
+        let glyph = u8::from(glyph);
+        self.level.iter().filter(|g| **g == glyph).count()
+    }
+}
+
+/// What the lobby tells clients about a map in the catalog
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MapSummary {
+    pub name: String,
+    pub width: u8,
+    pub height: usize,
+    #[serde(rename = "playerSlots")]
+    pub player_slots: usize,
 }
 
-impl From<char> for Glyph {
-    fn from(value: char) -> Self {
-        match value {
-            'X' => Glyph::Target,
-            '_' => Glyph::Water,
-            'T' => Glyph::Tree,
-            '*' => Glyph::Rock,
-            '.' => Glyph::Floor,
-            'P' => Glyph::Player,
-            'G' => Glyph::Monster,
-            'g' => Glyph::DefeatedMonster,
-            _ => panic!("Unexpected character {value} found for glyph."),
+impl MapSummary {
+    pub fn new(name: &str, map: &GameMap) -> Self {
+        Self {
+            name: name.to_string(),
+            width: map.width,
+            height: map.level.len() / map.width.max(1) as usize,
+            player_slots: map.player_count(),
         }
     }
 }
 
+const GLYPHS: &[u8] = b"X_T*.PGg";
+
 const DEFAULT_MAP_SIZE: u8 = 16;
 const DEFAULT_CAMERA_WIDTH: u8 = 12;
 
@@ -106,16 +160,100 @@ const MAP_ROWS: [&str; 16] = [
 ];
 
 pub fn get_default_map() -> GameMap {
-    let map_content: String = MAP_ROWS.join("");
-    let level: Vec<u8> = map_content
-        .chars()
-        .map(|c| u8::from(Glyph::from(c)))
-        .collect();
-
     GameMap {
-        level,
+        level: MAP_ROWS.concat().into_bytes(),
         width: DEFAULT_MAP_SIZE,
         cell_width: 40,
         view_width: DEFAULT_CAMERA_WIDTH,
     }
 }
+
+/// Loads every `*.json` map in `dir`, keyed by file name without the extension.
+pub fn load_map_dir(dir: &Path) -> io::Result<BTreeMap<String, GameMap>> {
+    let mut maps = BTreeMap::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        let invalid = |reason: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), reason),
+            )
+        };
+        let map: GameMap =
+            serde_json::from_slice(&std::fs::read(&path)?).map_err(|e| invalid(e.to_string()))?;
+        map.validate()
+            .map_err(|reason| invalid(reason.to_string()))?;
+
+        maps.insert(name.to_string(), map);
+    }
+
+    Ok(maps)
+}
+
+#[test]
+fn load_maps_from_dir() {
+    let dir = std::env::temp_dir().join(format!("flashes-maps-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("tiny.json"),
+        r#"{"level":[80,46,71,88],"width":2,"cellWidth":40,"viewWidth":2}"#,
+    )
+    .unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a map").unwrap();
+
+    let maps = load_map_dir(&dir).unwrap();
+    assert_eq!(maps.keys().collect::<Vec<_>>(), vec!["tiny"]);
+    assert_eq!(maps["tiny"].level, b"P.GX");
+
+    std::fs::write(
+        dir.join("broken.json"),
+        r#"{"level":[46,46,46,88],"width":2,"cellWidth":40,"viewWidth":2}"#,
+    )
+    .unwrap();
+    assert!(load_map_dir(&dir).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn parse_custom_map_rows() {
+    let map = GameMap::from_rows("P.T\n.GX\n...\n", None).unwrap();
+    assert_eq!(map.level, b"P.T.GX...");
+    assert_eq!(map.width, 3);
+    assert_eq!(map.view_width, 3);
+    assert!(map.validate().is_ok());
+
+    assert!(GameMap::from_rows("P.\n.GX", None).is_err());
+}
+
+#[test]
+fn reject_invalid_maps() {
+    assert!(get_default_map().validate().is_ok());
+
+    for (rows, reason) in [
+        ("..\nGX", "level needs at least one P"),
+        ("P.\n.X", "level needs at least one G"),
+        ("P.\nG.", "level needs at least one X"),
+        ("P.?\nG.X\n...", "level contains an unknown glyph"),
+        // the camera can't show a map taller or wider than it is
+        ("P.G\n..X", "level must have as many rows as columns"),
+        // walled in by trees and water
+        ("PT.\nT_X\nG..", "G needs a path to a P"),
+        ("P.T\n.GT\nTTX", "every P needs a path to an X"),
+        ("P.X\n..T\nG_P", "every P needs a path to an X"),
+    ] {
+        let map = GameMap::from_rows(rows, None).unwrap();
+        assert_eq!(map.validate(), Err(reason), "{}", rows);
+    }
+
+    let too_narrow = GameMap::from_rows("P.\nGX", Some(3)).unwrap();
+    assert!(too_narrow.validate().is_err());
+}
@@ -1,8 +1,73 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 use crate::game::Role;
 use crate::maps::GameMap;
 
+/// Bumped whenever a message changes shape, `GameMap` included
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest client protocol the server still talks to
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+pub const SUPPORTED_FEATURES: [Feature; 3] = [
+    Feature::ResumeToken,
+    Feature::RoleChange,
+    Feature::MessagePack,
+];
+
+/// Optional parts of the protocol, negotiated when a client joins
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Feature {
+    /// `ClientAcknowledged.resumeToken`
+    ResumeToken,
+    /// `RoleChanged` after a spectator is promoted
+    RoleChange,
+    /// binary MessagePack frames instead of JSON text
+    MessagePack,
+    /// anything newer than this server
+    #[serde(other)]
+    Unknown,
+}
+
+/// How messages are written to a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl Encoding {
+    pub fn negotiate(features: &[Feature]) -> Self {
+        match features.contains(&Feature::MessagePack) {
+            true => Encoding::MessagePack,
+            false => Encoding::Json,
+        }
+    }
+}
+
+/// Features both sides support, the rest are left out.
+pub fn negotiate_features(requested: &[Feature]) -> Vec<Feature> {
+    SUPPORTED_FEATURES
+        .into_iter()
+        .filter(|feature| requested.contains(feature))
+        .collect()
+}
+
+/// Rejects clients outside the supported protocol range.
+pub fn check_protocol_version(protocol_version: u32) -> Result<(), ProtocolError> {
+    match (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
+        true => Ok(()),
+        false => Err(ProtocolError::new(
+            ErrorCode::IncompatibleVersion,
+            format!(
+                "client speaks protocol {}, server supports {} to {}, reload the page to update",
+                protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ),
+        )),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ServerMessage {
@@ -11,15 +76,330 @@ pub enum ServerMessage {
         map: GameMap,
         #[serde(rename = "clientId")]
         client_id: String,
+        /// code of the room the client was placed in
+        room: String,
+        /// index of the slot within the role, players control the `P` at the same index
+        slot: usize,
+        #[serde(rename = "protocolVersion")]
+        protocol_version: u32,
+        /// negotiated features, the client should only rely on these
+        features: Vec<Feature>,
+        /// sent back in `ClientJoined` after a reconnect to resume this identity
+        #[serde(rename = "resumeToken", skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+        /// posted with the outcome to `/matches` when the game ends
+        #[serde(rename = "reportToken")]
+        report_token: String,
     },
     PeerJoined {
         #[serde(rename = "peerId")]
         peer_id: String,
+        role: Role,
+        /// tells peers whose moves the peer's updates can carry
+        slot: usize,
+    },
+    PeerLeft {
+        #[serde(rename = "peerId")]
+        peer_id: String,
     },
+    /// sent to a spectator that was moved into a free player slot
+    RoleChanged { role: Role, slot: usize },
+    /// sent to everyone else in the room after a `RoleChanged`
+    PeerRoleChanged {
+        #[serde(rename = "peerId")]
+        peer_id: String,
+        role: Role,
+        slot: usize,
+    },
+    /// the server refused a client message
+    Error { code: ErrorCode, message: String },
+    /// an admin swapped the room's map, the game starts over on it
+    MapReset { map: GameMap },
+    /// the server is going away, connect again after the delay
+    ServerShuttingDown {
+        #[serde(rename = "reconnectAfterMs")]
+        reconnect_after_ms: u64,
+    },
+}
+
+impl ServerMessage {
+    /// The `type` tag, used to label metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerMessage::ClientAcknowledged { .. } => "ClientAcknowledged",
+            ServerMessage::PeerJoined { .. } => "PeerJoined",
+            ServerMessage::PeerLeft { .. } => "PeerLeft",
+            ServerMessage::RoleChanged { .. } => "RoleChanged",
+            ServerMessage::PeerRoleChanged { .. } => "PeerRoleChanged",
+            ServerMessage::Error { .. } => "Error",
+            ServerMessage::MapReset { .. } => "MapReset",
+            ServerMessage::ServerShuttingDown { .. } => "ServerShuttingDown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum ErrorCode {
+    /// not valid JSON, or missing fields
+    InvalidMessage,
+    UnknownMessageType,
+    RoomNotFound,
+    IncompatibleVersion,
+    SessionFull,
+    AlreadyJoined,
+    RateLimited,
+}
+
+/// Why a client message was refused, sent back as `ServerMessage::Error`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ProtocolError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<ProtocolError> for ServerMessage {
+    fn from(error: ProtocolError) -> Self {
+        ServerMessage::Error {
+            code: error.code,
+            message: error.message,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ClientMessage {
-    ClientJoined,
+    ClientJoined {
+        /// missing for clients that predate versioning
+        #[serde(default, rename = "protocolVersion")]
+        protocol_version: u32,
+        #[serde(default)]
+        features: Vec<Feature>,
+        /// room to join, quick-matches when missing
+        #[serde(default)]
+        room: Option<String>,
+        /// token from an earlier `ClientAcknowledged`, used to resume that identity
+        #[serde(default, rename = "resumeToken")]
+        resume_token: Option<String>,
+    },
+    /// any `type` this server doesn't know about
+    #[serde(other)]
+    Unknown,
+}
+
+impl ClientMessage {
+    /// The `type` tag, used to label metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientMessage::ClientJoined { .. } => "ClientJoined",
+            ClientMessage::Unknown => "Unknown",
+        }
+    }
+}
+
+#[cfg(test)]
+fn assert_snapshot(message: &ServerMessage, expected: &str) {
+    assert_eq!(serde_json::to_string(message).unwrap(), expected);
+}
+
+#[test]
+fn serialize_client_acknowledged() {
+    assert_snapshot(
+        &ServerMessage::ClientAcknowledged {
+            role: Role::Player,
+            map: GameMap {
+                level: vec![4, 5],
+                width: 2,
+                cell_width: 40,
+                view_width: 2,
+            },
+            client_id: "a".to_string(),
+            room: "ROOM".to_string(),
+            slot: 0,
+            protocol_version: 1,
+            features: vec![Feature::ResumeToken, Feature::RoleChange],
+            resume_token: Some("token".to_string()),
+            report_token: "report".to_string(),
+        },
+        r#"{"type":"ClientAcknowledged","role":"Player","map":{"level":[4,5],"width":2,"cellWidth":40,"viewWidth":2},"clientId":"a","room":"ROOM","slot":0,"protocolVersion":1,"features":["resumeToken","roleChange"],"resumeToken":"token","reportToken":"report"}"#,
+    );
+    assert_snapshot(
+        &ServerMessage::ClientAcknowledged {
+            role: Role::Spectator,
+            map: GameMap {
+                level: vec![],
+                width: 0,
+                cell_width: 40,
+                view_width: 0,
+            },
+            client_id: "a".to_string(),
+            room: "ROOM".to_string(),
+            slot: 1,
+            protocol_version: 1,
+            features: vec![],
+            resume_token: None,
+            report_token: "report".to_string(),
+        },
+        r#"{"type":"ClientAcknowledged","role":"Spectator","map":{"level":[],"width":0,"cellWidth":40,"viewWidth":0},"clientId":"a","room":"ROOM","slot":1,"protocolVersion":1,"features":[],"reportToken":"report"}"#,
+    );
+}
+
+#[test]
+fn serialize_peer_messages() {
+    assert_snapshot(
+        &ServerMessage::PeerJoined {
+            peer_id: "b".to_string(),
+            role: Role::Spectator,
+            slot: 0,
+        },
+        r#"{"type":"PeerJoined","peerId":"b","role":"Spectator","slot":0}"#,
+    );
+    assert_snapshot(
+        &ServerMessage::PeerLeft {
+            peer_id: "b".to_string(),
+        },
+        r#"{"type":"PeerLeft","peerId":"b"}"#,
+    );
+    assert_snapshot(
+        &ServerMessage::RoleChanged {
+            role: Role::Player,
+            slot: 0,
+        },
+        r#"{"type":"RoleChanged","role":"Player","slot":0}"#,
+    );
+    assert_snapshot(
+        &ServerMessage::PeerRoleChanged {
+            peer_id: "b".to_string(),
+            role: Role::Player,
+            slot: 0,
+        },
+        r#"{"type":"PeerRoleChanged","peerId":"b","role":"Player","slot":0}"#,
+    );
+}
+
+#[test]
+fn serialize_error() {
+    assert_snapshot(
+        &ProtocolError::new(ErrorCode::SessionFull, "full").into(),
+        r#"{"type":"Error","code":"SessionFull","message":"full"}"#,
+    );
+}
+
+#[test]
+fn serialize_map_reset() {
+    assert_snapshot(
+        &ServerMessage::MapReset {
+            map: GameMap {
+                level: vec![4, 5],
+                width: 2,
+                cell_width: 40,
+                view_width: 2,
+            },
+        },
+        r#"{"type":"MapReset","map":{"level":[4,5],"width":2,"cellWidth":40,"viewWidth":2}}"#,
+    );
+}
+
+#[test]
+fn serialize_server_shutting_down() {
+    assert_snapshot(
+        &ServerMessage::ServerShuttingDown {
+            reconnect_after_ms: 5000,
+        },
+        r#"{"type":"ServerShuttingDown","reconnectAfterMs":5000}"#,
+    );
+}
+
+#[test]
+fn deserialize_client_joined() {
+    let message: ClientMessage = serde_json::from_str(
+        r#"{"type":"ClientJoined","protocolVersion":1,"features":["resumeToken","teleport"],"resumeToken":"token"}"#,
+    )
+    .unwrap();
+    assert!(matches!(
+        message,
+        ClientMessage::ClientJoined {
+            protocol_version: 1,
+            ref features,
+            room: None,
+            resume_token: Some(ref token),
+        } if *features == [Feature::ResumeToken, Feature::Unknown] && token == "token"
+    ));
+
+    // clients from before versioning
+    let message: ClientMessage = serde_json::from_str(r#"{"type":"ClientJoined"}"#).unwrap();
+    assert!(matches!(
+        message,
+        ClientMessage::ClientJoined {
+            protocol_version: 0,
+            ref features,
+            room: None,
+            resume_token: None,
+        } if features.is_empty()
+    ));
+
+    let message: ClientMessage = serde_json::from_str(r#"{"type":"Teleport"}"#).unwrap();
+    assert!(matches!(message, ClientMessage::Unknown));
+}
+
+#[test]
+fn negotiate_shared_features() {
+    assert_eq!(
+        negotiate_features(&[Feature::Unknown, Feature::RoleChange]),
+        vec![Feature::RoleChange]
+    );
+    assert!(check_protocol_version(PROTOCOL_VERSION).is_ok());
+    assert!(matches!(
+        check_protocol_version(MIN_PROTOCOL_VERSION - 1),
+        Err(ProtocolError {
+            code: ErrorCode::IncompatibleVersion,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn round_trip_message_pack() {
+    let message = ServerMessage::RoleChanged {
+        role: Role::Player,
+        slot: 0,
+    };
+    let bytes = rmp_serde::to_vec_named(&message).unwrap();
+    let decoded: ServerMessage = rmp_serde::from_slice(&bytes).unwrap();
+    assert!(matches!(
+        decoded,
+        ServerMessage::RoleChanged {
+            role: Role::Player,
+            slot: 0
+        }
+    ));
+
+    let joined = rmp_serde::to_vec_named(&serde_json::json!({
+        "type": "ClientJoined",
+        "protocolVersion": 1,
+        "features": ["messagePack"],
+    }))
+    .unwrap();
+    let decoded: ClientMessage = rmp_serde::from_slice(&joined).unwrap();
+    assert!(matches!(
+        decoded,
+        ClientMessage::ClientJoined { ref features, .. } if *features == [Feature::MessagePack]
+    ));
 }
@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::game::Role;
+use crate::lobby::Lobby;
+
+/// upper bounds of the session duration buckets, in seconds
+const SESSION_DURATION_BUCKETS: [f64; 8] = [10.0, 30.0, 60.0, 300.0, 600.0, 1800.0, 3600.0, 7200.0];
+
+/// Counters that can't be read off the lobby, rendered by `/metrics`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// open websockets, joined a room or not
+    connections: AtomicU64,
+    received: Mutex<BTreeMap<&'static str, u64>>,
+    sent: Mutex<BTreeMap<&'static str, u64>>,
+    session_durations: Mutex<Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// non-cumulative, one per bucket plus one past the last
+    counts: [u64; SESSION_DURATION_BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        let bucket = SESSION_DURATION_BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(SESSION_DURATION_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+    }
+}
+
+impl Metrics {
+    pub fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self, duration: Duration) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+        self.session_durations
+            .lock()
+            .unwrap()
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn message_received(&self, kind: &'static str) {
+        *self.received.lock().unwrap().entry(kind).or_default() += 1;
+    }
+
+    pub fn message_sent(&self, kind: &'static str) {
+        *self.sent.lock().unwrap().entry(kind).or_default() += 1;
+    }
+
+    /// Prometheus text format, room and client gauges are read off `lobby`.
+    pub fn render(&self, lobby: &Lobby) -> String {
+        let mut out = String::new();
+
+        gauge(
+            &mut out,
+            "flashes_connections",
+            "Open websocket connections",
+            self.connections.load(Ordering::Relaxed),
+        );
+
+        let _ = writeln!(out, "# HELP flashes_clients Clients in a room by role");
+        let _ = writeln!(out, "# TYPE flashes_clients gauge");
+        for role in [Role::Player, Role::Spectator] {
+            let count = lobby
+                .rooms
+                .iter()
+                .flat_map(|room| room.session.clients())
+                .filter(|(r, _)| *r == role)
+                .count();
+            let _ = writeln!(out, "flashes_clients{{role=\"{:?}\"}} {}", role, count);
+        }
+
+        gauge(
+            &mut out,
+            "flashes_rooms",
+            "Rooms in the lobby",
+            lobby.rooms.len() as u64,
+        );
+        gauge(
+            &mut out,
+            "flashes_rooms_waiting",
+            "Rooms with one client waiting for a peer",
+            lobby.rooms.iter().filter(|room| room.is_waiting()).count() as u64,
+        );
+
+        counters(
+            &mut out,
+            "flashes_messages_received_total",
+            "Client messages by type",
+            &self.received.lock().unwrap(),
+        );
+        counters(
+            &mut out,
+            "flashes_messages_sent_total",
+            "Server messages by type",
+            &self.sent.lock().unwrap(),
+        );
+
+        let durations = self.session_durations.lock().unwrap();
+        let name = "flashes_session_duration_seconds";
+        let _ = writeln!(out, "# HELP {} How long connections stayed open", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, count) in SESSION_DURATION_BUCKETS.iter().zip(durations.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let total: u64 = durations.counts.iter().sum();
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, total);
+        let _ = writeln!(out, "{}_sum {}", name, durations.sum);
+        let _ = writeln!(out, "{}_count {}", name, total);
+
+        out
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn counters(out: &mut String, name: &str, help: &str, values: &BTreeMap<&'static str, u64>) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (kind, value) in values {
+        let _ = writeln!(out, "{}{{type=\"{}\"}} {}", name, kind, value);
+    }
+}
+
+#[test]
+fn render_counters_and_histogram() {
+    let metrics = Metrics::default();
+    metrics.connection_opened();
+    metrics.connection_opened();
+    metrics.connection_closed(Duration::from_secs(45));
+    metrics.message_received("ClientJoined");
+    metrics.message_received("ClientJoined");
+    metrics.message_sent("ClientAcknowledged");
+
+    let rendered = metrics.render(&Lobby::default());
+
+    for line in [
+        "flashes_connections 1",
+        "flashes_clients{role=\"Player\"} 0",
+        "flashes_rooms 0",
+        "flashes_messages_received_total{type=\"ClientJoined\"} 2",
+        "flashes_messages_sent_total{type=\"ClientAcknowledged\"} 1",
+        "flashes_session_duration_seconds_bucket{le=\"30\"} 0",
+        "flashes_session_duration_seconds_bucket{le=\"60\"} 1",
+        "flashes_session_duration_seconds_bucket{le=\"+Inf\"} 1",
+        "flashes_session_duration_seconds_count 1",
+    ] {
+        assert!(rendered.lines().any(|l| l == line), "missing {}", line);
+    }
+}
@@ -0,0 +1,199 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// One entry of the allowlist, like `https://example.com` or `https://*.example.com`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllowedOrigin {
+    scheme: String,
+    host: String,
+    port: u16,
+    /// matches any subdomain of `host`, but not `host` itself
+    wildcard_subdomains: bool,
+}
+
+/// An origin as sent by a browser, `scheme://host[:port]` and nothing else
+#[derive(Debug, Clone, PartialEq)]
+struct Origin {
+    scheme: String,
+    host: String,
+    port: u16,
+}
+
+impl FromStr for Origin {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let uri: Uri = value
+            .parse()
+            .map_err(|_| format!("{} is not a URL", value))?;
+
+        let scheme = uri
+            .scheme_str()
+            .ok_or_else(|| format!("{} has no scheme", value))?
+            .to_ascii_lowercase();
+        let port = match (scheme.as_str(), uri.port_u16()) {
+            (_, Some(port)) => port,
+            ("http", None) => 80,
+            ("https", None) => 443,
+            _ => return Err(format!("{} must be http or https", value)),
+        };
+
+        let authority = uri
+            .authority()
+            .ok_or_else(|| format!("{} has no host", value))?;
+        // user info could make `https://example.com@evil.com` look like example.com
+        if authority.as_str().contains('@') {
+            return Err(format!("{} has user info", value));
+        }
+        if uri
+            .path_and_query()
+            .is_some_and(|path| path.as_str() != "/")
+        {
+            return Err(format!("{} has a path", value));
+        }
+
+        Ok(Self {
+            scheme,
+            host: authority.host().to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+impl FromStr for AllowedOrigin {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (value, wildcard_subdomains) = match value.split_once("://*.") {
+            Some((scheme, host)) => (format!("{}://{}", scheme, host), true),
+            None => (value.to_string(), false),
+        };
+        let origin: Origin = value.parse()?;
+
+        Ok(Self {
+            scheme: origin.scheme,
+            host: origin.host,
+            port: origin.port,
+            wildcard_subdomains,
+        })
+    }
+}
+
+impl AllowedOrigin {
+    fn matches(&self, origin: &Origin) -> bool {
+        let host_matches = match self.wildcard_subdomains {
+            true => origin
+                .host
+                .strip_suffix(&self.host)
+                .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+            false => origin.host == self.host,
+        };
+
+        host_matches && origin.scheme == self.scheme && origin.port == self.port
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OriginAllowlist(Vec<AllowedOrigin>);
+
+impl OriginAllowlist {
+    pub fn parse<S: AsRef<str>>(origins: &[S]) -> Result<Self, String> {
+        origins
+            .iter()
+            .map(|origin| origin.as_ref().parse())
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// Exact scheme, host and port match against one of the entries.
+    pub fn allows(&self, origin: &str) -> bool {
+        let Ok(origin) = origin.parse::<Origin>() else {
+            return false;
+        };
+        self.0.iter().any(|allowed| allowed.matches(&origin))
+    }
+}
+
+/// Turns away requests whose `Origin` isn't on the allowlist.
+///
+/// Requests without one are let through, browsers always send it so only
+/// non-browser clients can leave it out.
+pub async fn require_allowed_origin(
+    State(allowlist): State<Arc<OriginAllowlist>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(origin) = request.headers().get(header::ORIGIN) {
+        let allowed = origin.to_str().is_ok_and(|origin| allowlist.allows(origin));
+        if !allowed {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+    }
+
+    next.run(request).await
+}
+
+#[test]
+fn allow_exact_origins_only() {
+    let allowlist =
+        OriginAllowlist::parse(&["https://example.com", "http://localhost:3000"]).unwrap();
+
+    assert!(allowlist.allows("https://example.com"));
+    assert!(allowlist.allows("https://EXAMPLE.com:443"));
+    assert!(allowlist.allows("http://localhost:3000"));
+
+    for bypass in [
+        "https://evilexample.com",
+        "https://example.com.evil.com",
+        "https://sub.example.com",
+        "http://example.com",
+        "https://example.com:8443",
+        "https://example.com@evil.com",
+        "https://example.com/path",
+        "http://localhost:3001",
+        "http://localhost",
+        "null",
+        "",
+    ] {
+        assert!(!allowlist.allows(bypass), "{} should be rejected", bypass);
+    }
+}
+
+#[test]
+fn allow_wildcard_subdomains_when_listed() {
+    let allowlist = OriginAllowlist::parse(&["https://*.example.com"]).unwrap();
+
+    assert!(allowlist.allows("https://a.example.com"));
+    assert!(allowlist.allows("https://a.b.example.com"));
+
+    for bypass in [
+        "https://example.com",
+        "https://evilexample.com",
+        "https://.example.com",
+        "https://a.example.com.evil.com",
+        "http://a.example.com",
+    ] {
+        assert!(!allowlist.allows(bypass), "{} should be rejected", bypass);
+    }
+}
+
+#[test]
+fn reject_malformed_allowlist_entries() {
+    for entry in [
+        "example.com",
+        "ftp://example.com",
+        "https://example.com/app",
+    ] {
+        assert!(
+            OriginAllowlist::parse(&[entry]).is_err(),
+            "{} should not parse",
+            entry
+        );
+    }
+}
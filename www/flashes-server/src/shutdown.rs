@@ -0,0 +1,85 @@
+use std::future::{Future, IntoFuture};
+use std::time::Duration;
+
+use axum::Router;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+
+use crate::game::ClientSender;
+use crate::lobby::SharedState;
+use crate::messages::ServerMessage;
+
+/// how often draining checks whether the outbound queues are empty
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Tells every client the server is going away and stops taking new connections.
+///
+/// Waits up to the configured drain timeout for the notice and anything queued before it
+/// to leave the outbound queues, returns whether they all emptied in time.
+pub async fn drain(state: &SharedState) -> bool {
+    let (senders, drain_timeout) = {
+        let mut lobby = state.lock().await;
+        lobby.shutting_down = true;
+
+        let message = ServerMessage::ServerShuttingDown {
+            reconnect_after_ms: lobby.config.shutdown_reconnect_after.as_millis() as u64,
+        };
+        let senders: Vec<ClientSender> = lobby
+            .rooms
+            .iter()
+            .flat_map(|room| room.session.clients())
+            .map(|(_, client)| client.sender.clone())
+            .collect();
+        for sender in &senders {
+            sender.send(message.clone());
+        }
+
+        (senders, lobby.config.shutdown_drain_timeout)
+    };
+    tracing::info!(clients = senders.len(), "shutting down");
+
+    let drained = tokio::time::timeout(drain_timeout, async {
+        while !senders.iter().all(ClientSender::is_drained) {
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    })
+    .await
+    .is_ok();
+    if !drained {
+        tracing::warn!("outbound queues didn't drain in time");
+    }
+
+    drained
+}
+
+/// Serves until `signal` resolves, then drains before the listener is closed.
+///
+/// Connections keep being accepted while draining, so late upgrades get refused instead
+/// of hanging.
+pub async fn serve_until(
+    listener: TcpListener,
+    router: Router,
+    state: SharedState,
+    signal: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    let (stop, stopped) = oneshot::channel();
+    let mut server = std::pin::pin!(axum::serve(listener, router)
+        .with_graceful_shutdown(async {
+            let _ = stopped.await;
+        })
+        .into_future());
+
+    tokio::select! {
+        result = &mut server => return result,
+        () = signal => {}
+    }
+    let ((), result) = tokio::join!(
+        async {
+            drain(&state).await;
+            let _ = stop.send(());
+        },
+        server
+    );
+
+    result
+}
@@ -0,0 +1,88 @@
+//! Helpers shared by the tests of several modules.
+
+use axum::{body::Body, http::StatusCode, Router};
+use http_body_util::BodyExt;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{mpsc, Mutex};
+use tower::ServiceExt;
+
+use crate::game::{Client, ClientSender};
+use crate::lobby::{Lobby, SharedState};
+use crate::messages::{ServerMessage, SUPPORTED_FEATURES};
+
+/// A client supporting every feature, nothing reads what it's sent.
+pub fn test_client(id: &str) -> Client {
+    let (sender, _) = ClientSender::new(1);
+    Client {
+        id: id.to_string(),
+        sender,
+        features: SUPPORTED_FEATURES.to_vec(),
+        connected_at: Instant::now(),
+    }
+}
+
+/// A lobby with one room on the default map, joined by `client_ids` in order.
+///
+/// Returns the room's code and each client's sender with what it was sent.
+pub fn state_with_room(
+    client_ids: &[&str],
+) -> (
+    SharedState,
+    String,
+    Vec<(ClientSender, mpsc::Receiver<ServerMessage>)>,
+) {
+    let mut lobby = Lobby::default();
+    let map = lobby.default_map();
+    let room = lobby.create_room(map, Instant::now());
+    let clients = client_ids
+        .iter()
+        .map(|id| {
+            let (sender, receiver) = ClientSender::new(4);
+            room.session.join(
+                Client {
+                    sender: sender.clone(),
+                    ..test_client(id)
+                },
+                Instant::now(),
+            );
+            (sender, receiver)
+        })
+        .collect();
+    let code = room.code.clone();
+
+    (Arc::new(Mutex::new(lobby)), code, clients)
+}
+
+/// Sends a JSON request through `router`, with `Authorization: Bearer <token>` if given.
+pub async fn send_request(
+    router: Router<SharedState>,
+    state: &SharedState,
+    method: &str,
+    uri: &str,
+    token: Option<&str>,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, Option<serde_json::Value>) {
+    let mut request = axum::http::Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json");
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    let request = match body {
+        Some(body) => request.body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .unwrap();
+
+    let response = router
+        .with_state(state.clone())
+        .oneshot(request)
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, serde_json::from_slice(&bytes).ok())
+}
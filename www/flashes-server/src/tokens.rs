@@ -0,0 +1,157 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+pub const DEFAULT_RESUME_TOKEN_TTL: Duration = Duration::from_secs(60 * 60);
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Identity carried by a valid resume token
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumeClaims {
+    pub client_id: String,
+    /// code of the room the client was in
+    pub room: String,
+    /// seconds since the unix epoch
+    pub expires_at: u64,
+}
+
+/// Issues and verifies the tokens clients use to resume their identity after reconnecting.
+///
+/// A token is `<payload>.<signature>`, both base64url encoded, where the payload is
+/// `<client id>:<room code>:<expiry>` and the signature is an HMAC-SHA256 of the payload.
+#[derive(Clone)]
+pub struct ResumeTokens {
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+impl ResumeTokens {
+    pub fn new(secret: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        Self {
+            secret: secret.into(),
+            ttl,
+        }
+    }
+
+    /// Signs with a random secret, tokens won't survive a server restart.
+    pub fn random(ttl: Duration) -> Self {
+        let secret = [Uuid::new_v4().into_bytes(), Uuid::new_v4().into_bytes()].concat();
+        Self::new(secret, ttl)
+    }
+
+    pub fn issue(&self, client_id: &str, room: &str, now: SystemTime) -> String {
+        let expires_at = unix_secs(now) + self.ttl.as_secs();
+        let payload = format!("{}:{}:{}", client_id, room, expires_at);
+        let signature = self.mac(payload.as_bytes()).finalize().into_bytes();
+
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    /// Returns the claims if the token was signed by us and hasn't expired.
+    pub fn verify(&self, token: &str, now: SystemTime) -> Option<ResumeClaims> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+        self.mac(&payload).verify_slice(&signature).ok()?;
+
+        let payload = String::from_utf8(payload).ok()?;
+        let (rest, expires_at) = payload.rsplit_once(':')?;
+        let (client_id, room) = rest.rsplit_once(':')?;
+        let expires_at: u64 = expires_at.parse().ok()?;
+
+        if expires_at <= unix_secs(now) {
+            return None;
+        }
+
+        Some(ResumeClaims {
+            client_id: client_id.to_string(),
+            room: room.to_string(),
+            expires_at,
+        })
+    }
+
+    fn mac(&self, payload: &[u8]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(payload);
+        mac
+    }
+}
+
+impl Default for ResumeTokens {
+    fn default() -> Self {
+        Self::random(DEFAULT_RESUME_TOKEN_TTL)
+    }
+}
+
+// keep the secret out of logs
+impl fmt::Debug for ResumeTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResumeTokens")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[test]
+fn verify_issued_token() {
+    let tokens = ResumeTokens::new("secret", DEFAULT_RESUME_TOKEN_TTL);
+    let now = SystemTime::now();
+
+    let token = tokens.issue("a", "ROOM", now);
+    let claims = tokens.verify(&token, now).expect("token is valid");
+
+    assert_eq!(claims.client_id, "a");
+    assert_eq!(claims.room, "ROOM");
+    assert_eq!(
+        claims.expires_at,
+        unix_secs(now) + DEFAULT_RESUME_TOKEN_TTL.as_secs()
+    );
+}
+
+#[test]
+fn reject_expired_token() {
+    let tokens = ResumeTokens::new("secret", DEFAULT_RESUME_TOKEN_TTL);
+    let now = SystemTime::now();
+
+    let token = tokens.issue("a", "ROOM", now);
+
+    assert!(tokens
+        .verify(&token, now + DEFAULT_RESUME_TOKEN_TTL)
+        .is_none());
+}
+
+#[test]
+fn reject_tampered_token() {
+    let tokens = ResumeTokens::new("secret", DEFAULT_RESUME_TOKEN_TTL);
+    let now = SystemTime::now();
+
+    let token = tokens.issue("a", "ROOM", now);
+    let (_, signature) = token.split_once('.').unwrap();
+    let forged = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode("b:ROOM:99999999999"),
+        signature
+    );
+
+    assert!(tokens.verify(&forged, now).is_none());
+    assert!(tokens.verify("garbage", now).is_none());
+    // signed with another secret
+    let other = ResumeTokens::new("other", DEFAULT_RESUME_TOKEN_TTL);
+    assert!(other.verify(&token, now).is_none());
+}
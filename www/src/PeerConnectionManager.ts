@@ -1,5 +1,6 @@
 import Peer, { DataConnection } from 'peerjs';
 import type { GameMap } from './state';
+import { StorageKeysEnum, getStorage, setStorage } from './storage';
 
 enum Role {
   Player = 'Player',
@@ -12,9 +13,22 @@ export interface ConnectionInfo {
   clientId: string;
 }
 
+// must match the server, bump whenever a message changes shape
+export const PROTOCOL_VERSION = 1;
+
+export type Feature = 'resumeToken' | 'roleChange' | 'messagePack';
+// no MessagePack decoder here yet, stick to JSON
+const SUPPORTED_FEATURES: Feature[] = ['resumeToken', 'roleChange'];
+
 export enum ServerMessageType {
   ClientAcknowledged = 'ClientAcknowledged',
   PeerJoined = 'PeerJoined',
+  PeerLeft = 'PeerLeft',
+  RoleChanged = 'RoleChanged',
+  PeerRoleChanged = 'PeerRoleChanged',
+  Error = 'Error',
+  MapReset = 'MapReset',
+  ServerShuttingDown = 'ServerShuttingDown',
 }
 
 interface ClientAcknowledgedMessage {
@@ -22,24 +36,83 @@ interface ClientAcknowledgedMessage {
   role: Role;
   map: GameMap;
   clientId: string;
+  room: string;
+  slot: number;
+  protocolVersion: number;
+  features: Feature[];
+  resumeToken?: string;
+  reportToken: string;
 }
 
 interface PeerJoinedMessage {
   type: ServerMessageType.PeerJoined;
   peerId: string;
+  role: Role;
+  slot: number;
+}
+
+interface PeerLeftMessage {
+  type: ServerMessageType.PeerLeft;
+  peerId: string;
+}
+
+interface RoleChangedMessage {
+  type: ServerMessageType.RoleChanged;
+  role: Role;
+  slot: number;
+}
+
+interface PeerRoleChangedMessage {
+  type: ServerMessageType.PeerRoleChanged;
+  peerId: string;
+  role: Role;
+  slot: number;
+}
+
+export type ErrorCode =
+  | 'InvalidMessage'
+  | 'UnknownMessageType'
+  | 'RoomNotFound'
+  | 'IncompatibleVersion'
+  | 'SessionFull'
+  | 'AlreadyJoined'
+  | 'RateLimited';
+
+interface ErrorMessage {
+  type: ServerMessageType.Error;
+  code: ErrorCode;
+  message: string;
+}
+
+interface MapResetMessage {
+  type: ServerMessageType.MapReset;
+  map: GameMap;
+}
+
+interface ServerShuttingDownMessage {
+  type: ServerMessageType.ServerShuttingDown;
+  reconnectAfterMs: number;
 }
 
+export type Outcome = 'Won' | 'Lost';
+
 export enum ClientMessage {
   ClientJoined = 'ClientJoined',
 }
 
 interface ClientJoinedMessage {
   type: ClientMessage.ClientJoined;
+  protocolVersion: number;
+  features: Feature[];
+  // quick-matches into a waiting room when missing
+  room?: string;
+  resumeToken?: string;
 }
 
 export enum P2PMessageType {
   InitialStateVector = 'InitialStateVector',
   Delta = 'Delta',
+  RequestStateVector = 'RequestStateVector',
 }
 
 export interface InitialStateVectorMessage {
@@ -52,8 +125,32 @@ export interface DeltaMessage {
   data: Uint8Array;
 }
 
-type ServerMessage = ClientAcknowledgedMessage | PeerJoinedMessage;
-type P2PMessage = InitialStateVectorMessage | DeltaMessage;
+// sent after refusing a delta, the first player answers with InitialStateVector
+export interface RequestStateVectorMessage {
+  type: P2PMessageType.RequestStateVector;
+}
+
+type ServerMessage =
+  | ClientAcknowledgedMessage
+  | PeerJoinedMessage
+  | PeerLeftMessage
+  | RoleChangedMessage
+  | PeerRoleChangedMessage
+  | ErrorMessage
+  | MapResetMessage
+  | ServerShuttingDownMessage;
+type P2PMessage =
+  | InitialStateVectorMessage
+  | DeltaMessage
+  | RequestStateVectorMessage;
+
+// the engine checks a delta only touches what its sender controls
+export interface PeerInfo {
+  role: Role;
+  slot: number;
+}
+// peers the server never announced are treated as spectators
+type ReceivedP2PMessage = P2PMessage & { sender: PeerInfo | undefined };
 
 export class PeerConnectionManager {
   peerConnectionStatus: 'Waiting' | 'Connected' | 'Disconnected' = 'Waiting';
@@ -62,14 +159,15 @@ export class PeerConnectionManager {
   private p2pConnection: DataConnection | null = null;
   private role: Role | undefined;
   private clientId: string | undefined;
+  private peers = new Map<string, PeerInfo>();
 
   private serverStreamController: ReadableStreamDefaultController<ServerMessage> | null =
     null;
   serverStream: ReadableStream<ServerMessage>;
 
-  private peerStreamController: ReadableStreamDefaultController<P2PMessage> | null =
+  private peerStreamController: ReadableStreamDefaultController<ReceivedP2PMessage> | null =
     null;
-  peerStream: ReadableStream<P2PMessage>;
+  peerStream: ReadableStream<ReceivedP2PMessage>;
 
   constructor() {
     this.serverStream = new ReadableStream<ServerMessage>({
@@ -77,7 +175,7 @@ export class PeerConnectionManager {
         this.serverStreamController = controller;
       },
     });
-    this.peerStream = new ReadableStream<P2PMessage>({
+    this.peerStream = new ReadableStream<ReceivedP2PMessage>({
       start: (controller) => {
         this.peerStreamController = controller;
       },
@@ -87,8 +185,13 @@ export class PeerConnectionManager {
   async connect(): Promise<void> {
     this.serverConnection = new WebSocket(SERVER_URL);
 
+    // lets the server restore our identity and slot after a refresh
+    const resumeToken = getStorage(StorageKeysEnum.RESUME_TOKEN);
     const joinMessage: ClientJoinedMessage = {
       type: ClientMessage.ClientJoined,
+      protocolVersion: PROTOCOL_VERSION,
+      features: SUPPORTED_FEATURES,
+      ...(resumeToken ? { resumeToken } : {}),
     };
 
     return new Promise((resolve, reject) => {
@@ -109,8 +212,38 @@ export class PeerConnectionManager {
           case ServerMessageType.ClientAcknowledged:
             this.clientId = message.clientId;
             this.role = message.role;
+            setStorage(StorageKeysEnum.RESUME_TOKEN, message.resumeToken ?? null);
+            setStorage(StorageKeysEnum.REPORT_TOKEN, message.reportToken);
+            break;
+          case ServerMessageType.RoleChanged:
+            this.role = message.role;
+            break;
+          case ServerMessageType.Error:
+            console.error(`Server error ${message.code}: ${message.message}`);
+            break;
+          case ServerMessageType.MapReset:
+            // the engine is built for one map, rejoin to get a fresh one
+            // the resume token keeps our slot
+            window.location.reload();
+            break;
+          case ServerMessageType.ServerShuttingDown:
+            // rejoin once the new server is up, the resume token keeps our identity
+            setTimeout(() => window.location.reload(), message.reconnectAfterMs);
+            break;
+          case ServerMessageType.PeerRoleChanged:
+            this.peers.set(message.peerId, {
+              role: message.role,
+              slot: message.slot,
+            });
+            break;
+          case ServerMessageType.PeerLeft:
+            this.peers.delete(message.peerId);
             break;
           case ServerMessageType.PeerJoined:
+            this.peers.set(message.peerId, {
+              role: message.role,
+              slot: message.slot,
+            });
             if (this.role === 'Player') this.peerConnectionStatus = 'Connected';
             await this.initializePeerConnection(message.peerId);
             break;
@@ -130,12 +263,21 @@ export class PeerConnectionManager {
     });
   }
 
-  // closes the connection to the server
-  // ideally after P2P connection with the peer has been established
-  closeServerConnection() {
-    this.serverStreamController?.close();
-    this.serverStream.cancel();
-    this.serverStreamController = null;
+  // goes over HTTP so the outcome still gets through if the websocket dropped
+  async reportOutcome(outcome: Outcome) {
+    const reportToken = getStorage(StorageKeysEnum.REPORT_TOKEN);
+    if (!reportToken) return;
+
+    const matchesUrl = new URL('/matches', SERVER_URL.replace(/^ws/, 'http'));
+    try {
+      await fetch(matchesUrl, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ reportToken, outcome }),
+      });
+    } catch (error) {
+      console.error('Failed to report outcome:', error);
+    }
   }
 
   // this should happen after receiving PeerJoinedEvent
@@ -185,6 +327,7 @@ export class PeerConnectionManager {
         this.peerConnectionStatus = 'Connected';
         this.peerStreamController.enqueue({
           ...(data as P2PMessage),
+          sender: this.peers.get(dataConnection.peer),
         });
       }
     });
@@ -201,7 +344,7 @@ export class PeerConnectionManager {
     });
   }
 
-  sendToPeer(message: InitialStateVectorMessage | DeltaMessage): void {
+  sendToPeer(message: P2PMessage): void {
     if (!this.p2pConnection?.open) return;
 
     this.p2pConnection.send(message);
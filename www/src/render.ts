@@ -1,5 +1,6 @@
 import Stream from 'rextream';
 
+import { TileVisibility } from '../engine/flashlight';
 import {
   DEFEATED_MONSTER_GLYPH,
   GLYPHS,
@@ -55,6 +56,7 @@ export class Renderer {
   viewWidth: number | undefined;
   playerCellIdx: number | undefined;
   visibilityState: number[] | undefined;
+  tileVisibility: TileVisibility[] | undefined;
   cameraAnimationState: CameraAnimationState = {
     shake: Trauma.None,
     move: Pan.None,
@@ -87,12 +89,14 @@ export class Renderer {
   updateRendererState({
     gameMap,
     visibilityState,
+    tileVisibility,
     playerCellIdx,
     particleState,
     playerAnimationState,
   }: {
     gameMap: GameMap;
     visibilityState: number[];
+    tileVisibility: TileVisibility[];
     playerCellIdx: number;
     particleState: [Particle, Particle];
     playerAnimationState: PlayerAnimationState;
@@ -102,6 +106,7 @@ export class Renderer {
     this.viewWidth = gameMap.viewWidth;
     this.playerCellIdx = playerCellIdx;
     this.visibilityState = visibilityState;
+    this.tileVisibility = tileVisibility;
     this.particleState = [...particleState];
     this.playerAnimationState = { ...playerAnimationState };
   }
@@ -111,6 +116,7 @@ export class Renderer {
     if (
       !this.mapBuffer ||
       !this.visibilityState ||
+      !this.tileVisibility ||
       !this.mapWidth ||
       !this.viewWidth ||
       !this.playerCellIdx
@@ -181,6 +187,24 @@ export class Renderer {
 
       if (!cellVisibility || !cellFog) return;
 
+      cell.dataset.idx = `${cellIdx}`;
+
+      // never seen, there's nothing to show
+      if (this.tileVisibility?.[cellIdx] === TileVisibility.Unknown) {
+        cell.classList.add('pointer-none', 'cell', 'bg-black');
+        fragment.appendChild(cell);
+        return;
+      }
+
+      // seen before, the terrain stays dimmed
+      // spectators see everything, dimmed outside the players' light
+      if (
+        this.tileVisibility?.[cellIdx] === TileVisibility.Remembered ||
+        this.tileVisibility?.[cellIdx] === TileVisibility.Unlit
+      ) {
+        cell.style.opacity = '0.5';
+      }
+
       cell.textContent = displayGlyph;
       cell.style.backgroundColor = `\
         hsla(\
@@ -248,7 +272,6 @@ export class Renderer {
         cell.classList.add('text-transparent');
       }
 
-      cell.dataset.idx = `${cellIdx}`;
       fragment.appendChild(cell);
     });
 
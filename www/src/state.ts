@@ -1,5 +1,12 @@
 import Stream from 'rextream';
-import { Flashlight, MoveOutcome, Vec2 } from '../engine/flashlight';
+import {
+  Flashlight,
+  FlashlightError,
+  MonsterControl,
+  MoveOutcome,
+  Role,
+  Vec2,
+} from '../engine/flashlight';
 import {
   P2PMessageType,
   PeerConnectionManager,
@@ -49,6 +56,7 @@ export class GameState {
   private connectionManager: PeerConnectionManager;
 
   role: 'Player' | 'Spectator' = 'Spectator';
+  slot = 0;
   private map: GameMap | undefined;
   private flashlight: Flashlight | undefined;
   private renderer = new Renderer();
@@ -89,6 +97,7 @@ export class GameState {
       'restart-container',
     ) as HTMLElement,
     flashlightButtonEl: document.getElementById('flashlight') as HTMLElement,
+    batteryEl: document.getElementById('battery') as HTMLElement,
     particleContainer: document.getElementById('particles') as HTMLElement,
     gameContainer: document.getElementById('game-container') as HTMLElement,
     gridContainer: document.getElementById('grid-container') as HTMLElement,
@@ -102,6 +111,8 @@ export class GameState {
   weather = WEATHER[0];
   rain = RAINFALL[0];
   private pendingInitialStateVector: Uint8Array | null = null;
+  // spectators in the room, any of them gets to play the monster
+  private spectatorPeers = new Set<string>();
 
   private constructor(connectionManager: PeerConnectionManager) {
     this.connectionManager = connectionManager;
@@ -122,12 +133,51 @@ export class GameState {
         switch (value.type) {
           case ServerMessageType.ClientAcknowledged: {
             instance.role = value.role;
+            instance.slot = value.slot;
             instance.map = value.map;
             break;
           }
           case ServerMessageType.PeerJoined: {
-            // Peer connection established, currently handled directly via global state
-            instance.connectionManager.closeServerConnection();
+            // Peer connection is handled by the connection manager,
+            // the server connection stays open for role changes and restarts
+            if (value.role === 'Spectator') {
+              instance.spectatorPeers.add(value.peerId);
+              instance.syncMonsterControl();
+            }
+            break;
+          }
+          case ServerMessageType.PeerLeft: {
+            // the AI takes the monster back once the monster player is gone
+            instance.spectatorPeers.delete(value.peerId);
+            instance.syncMonsterControl();
+            break;
+          }
+          case ServerMessageType.RoleChanged: {
+            instance.changeRole(value.role, value.slot);
+            break;
+          }
+          case ServerMessageType.PeerRoleChanged: {
+            // a promoted spectator no longer plays the monster
+            instance.spectatorPeers.delete(value.peerId);
+            instance.syncMonsterControl();
+            break;
+          }
+          case ServerMessageType.Error: {
+            // show the reason instead of waiting forever
+            instance.uiState.playButtonEl.textContent = value.message;
+            break;
+          }
+          case ServerMessageType.MapReset: {
+            // the connection manager reloads the page to rejoin on the new map
+            break;
+          }
+          case ServerMessageType.ServerShuttingDown: {
+            instance.uiState.playButtonEl.textContent = 'Server restarting...';
+            if (instance.flashlight) {
+              instance.uiState.announcementTarget.classList.remove('opacity-0');
+              instance.uiState.announcementTarget.textContent =
+                'Server restarting, reconnecting shortly...';
+            }
             break;
           }
           default:
@@ -147,18 +197,51 @@ export class GameState {
         instance.updateConnectionStatusUI();
         switch (value.type) {
           case P2PMessageType.InitialStateVector: {
-            // player will not accept initial state vector
-            if (instance.role === 'Player') break;
-
-            // store pending state vector for spectator
-            instance.pendingInitialStateVector = value.data;
+            // the first player's map is the source of truth
+            if (instance.role === 'Player' && instance.slot === 0) break;
+
+            // store pending state vector until the engine is ready
+            if (!instance.flashlight) {
+              instance.pendingInitialStateVector = value.data;
+              break;
+            }
+
+            // answer to a resync request
+            try {
+              instance.flashlight.apply_initial_state_vector_js(
+                new Uint8Array(value.data),
+              );
+            } catch (error) {
+              instance.showDesync(error as FlashlightError);
+            }
+            instance.tick();
             break;
           }
           case P2PMessageType.Delta: {
-            instance.flashlight?.apply_delta_js(new Uint8Array(value.data));
+            const sender = value.sender ?? { role: 'Spectator', slot: 0 };
+            try {
+              instance.flashlight?.apply_delta_js(
+                new Uint8Array(value.data),
+                sender.role === 'Player' ? Role.Player : Role.Spectator,
+                sender.slot,
+              );
+            } catch (error) {
+              // the engine keeps its state, ask for the whole map to catch up
+              console.warn(
+                `Rejected delta: ${FlashlightError[error as FlashlightError]}`,
+              );
+              instance.connectionManager.sendToPeer({
+                type: P2PMessageType.RequestStateVector,
+              });
+            }
             instance.tick();
             break;
           }
+          case P2PMessageType.RequestStateVector: {
+            // only the first player sends anything
+            instance.flashlight?.share_state_vector();
+            break;
+          }
           default:
             console.warn('Unexpected message type');
         }
@@ -198,22 +281,81 @@ export class GameState {
       width,
       cellWidth,
       viewWidth,
+      this.role === 'Player' ? Role.Player : Role.Spectator,
+      this.role === 'Player' ? this.slot : 0,
     );
 
     // Apply pending initial state vector if it arrived before engine was ready
-    if (this.pendingInitialStateVector && this.role === 'Spectator') {
-      this.flashlight.apply_initial_state_vector_js(
-        new Uint8Array(this.pendingInitialStateVector),
-      );
+    // the first player ignores it, everyone else takes their map
+    if (this.pendingInitialStateVector) {
+      try {
+        this.flashlight.apply_initial_state_vector_js(
+          new Uint8Array(this.pendingInitialStateVector),
+        );
+      } catch (error) {
+        console.warn(
+          `Rejected initial state: ${FlashlightError[error as FlashlightError]}`,
+        );
+      }
       this.pendingInitialStateVector = null;
     }
 
+    this.syncMonsterControl();
+
+    // the engine starts with the flashlight on, match the opening dialog
+    if (
+      this.role === 'Player' &&
+      this.flashlight.is_flashlight_on !== this.playerState.isFlashlightOn
+    ) {
+      this.flashlight.toggle_flashlight();
+    }
+
     this.initRain();
 
     this.initializeUI();
     await this.tick();
   }
 
+  // the first player hands the monster to the spectators while there are any
+  private syncMonsterControl() {
+    if (!this.flashlight || this.role !== 'Player' || this.slot !== 0) return;
+
+    this.flashlight.set_monster_control(
+      this.spectatorPeers.size > 0
+        ? MonsterControl.Spectator
+        : MonsterControl.Ai,
+    );
+  }
+
+  // a spectator took over a player's slot, or the other way around
+  private async changeRole(role: 'Player' | 'Spectator', slot: number) {
+    this.role = role;
+    this.slot = slot;
+    if (!this.flashlight) return;
+
+    this.flashlight.set_role(
+      role === 'Player' ? Role.Player : Role.Spectator,
+      role === 'Player' ? slot : 0,
+    );
+    this.uiState.buttonsContainer.classList.toggle(
+      'opacity-0',
+      role === 'Spectator',
+    );
+    this.uiState.roleTab.classList.toggle('opacity-0', role === 'Player');
+    this.syncMonsterControl();
+    await this.tick();
+  }
+
+  // the peers can't agree on the map anymore, there's no point in playing on
+  private showDesync(error: FlashlightError) {
+    console.error(`Out of sync: ${FlashlightError[error]}`);
+    this.uiState.announcementTarget.classList.remove('opacity-0');
+    this.uiState.announcementTarget.textContent =
+      'Lost sync with the other player';
+    this.gameState.isGameOver = true;
+    this.setGameOver();
+  }
+
   private updateConnectionStatusUI() {
     switch (this.connectionManager.peerConnectionStatus) {
       case 'Waiting':
@@ -262,7 +404,7 @@ export class GameState {
 
     $moveKeyInput
       .filter(() => {
-        return this.role !== 'Spectator';
+        return this.role !== 'Spectator' || this.isPlayingMonster();
       })
       .map((e) => {
         e.preventDefault();
@@ -279,7 +421,7 @@ export class GameState {
 
     $dPadInput
       .filter(() => {
-        return this.role !== 'Spectator';
+        return this.role !== 'Spectator' || this.isPlayingMonster();
       })
       .map((e) => {
         e.preventDefault();
@@ -414,6 +556,18 @@ export class GameState {
         complete: () => {},
       });
 
+    // once found, a single press switches it on and off
+    const listenForFlashlightPresses = () => {
+      $flashlightButtonClick.subscribe({
+        next: this.onFlashlightPress,
+        complete: () => {},
+      });
+      $fKeyInput.subscribe({
+        next: this.onFlashlightPress,
+        complete: () => {},
+      });
+    };
+
     const flashlightTriggerUnsub = $flashlightButtonClick
       .withLatestFrom($fKeyInput)
       .filter(takeContinuousN(3))
@@ -430,10 +584,17 @@ export class GameState {
 
           flashlightTriggerUnsub();
           openingDialogUnsub();
+          listenForFlashlightPresses();
         },
         complete: () => {},
       });
 
+    // the opening dialog was already seen, the flashlight starts on
+    if (this.playerState.isFlashlightOn) {
+      this.uiState.flashlightButtonEl.textContent = FlashlightGlyphs.On;
+      listenForFlashlightPresses();
+    }
+
     // make rain
     $tenSecInterval.subscribe({
       next: (tick) => {
@@ -581,6 +742,36 @@ export class GameState {
     await this.moveWithDelta({ delta });
   };
 
+  private isPlayingMonster() {
+    return this.flashlight?.monster_control === MonsterControl.Spectator;
+  }
+
+  private moveMonsterWithDelta = async (delta: readonly [number, number]) => {
+    const { x, y } = idxToGridPosition(
+      this.engine.map_metadata.monster_cell_idx,
+      this.engine.width,
+    );
+    const cell = Vec2.new_with_data(x + delta[0], y + delta[1]);
+
+    try {
+      if (this.engine.do_move_monster(cell) === MoveOutcome.End) {
+        this.endGame();
+      }
+    } catch (error) {
+      switch (error as FlashlightError) {
+        case FlashlightError.GameOver:
+          this.endGame();
+          break;
+        default:
+          console.warn(
+            `Monster move refused: ${FlashlightError[error as FlashlightError]}`,
+          );
+      }
+    }
+
+    await this.tick();
+  };
+
   private moveWithDelta = async ({
     delta,
   }: {
@@ -588,6 +779,12 @@ export class GameState {
   }) => {
     if (!delta) return;
 
+    // the monster player's input moves the monster
+    if (this.role === 'Spectator') {
+      await this.moveMonsterWithDelta(delta);
+      return;
+    }
+
     const { x, y } = idxToGridPosition(
       this.engine.map_metadata.player_cell_idx,
       this.engine.width,
@@ -595,8 +792,6 @@ export class GameState {
     const gridPosition = { x: x + delta[0], y: y + delta[1] };
     const cell = Vec2.new_with_data(gridPosition.x, gridPosition.y);
 
-    const outcome: MoveOutcome = this.engine.do_move_player(cell);
-
     const playerMove =
       delta[0] < 0
         ? PlayerMove.PlayerMoveL
@@ -606,53 +801,100 @@ export class GameState {
             ? PlayerMove.PlayerMoveU
             : PlayerMove.PlayerMoveD;
 
-    switch (outcome) {
-      case MoveOutcome.Rejected: {
-        this.playerState.trauma =
-          delta[0] < 0
-            ? Trauma.TraumaL
-            : delta[0] > 0
-              ? Trauma.TraumaR
-              : delta[1] < 0
-                ? Trauma.TraumaU
-                : Trauma.TraumaD;
-        this.playerState.hop = false;
-        this.playerState.move = PlayerMove.None;
-
-        this.cameraState.shake = this.playerState.trauma;
-        break;
+    try {
+      const outcome: MoveOutcome = this.engine.do_move_player(cell);
+
+      switch (outcome) {
+        case MoveOutcome.Advance:
+          this.cameraState.shake = Trauma.None;
+          this.playerState.trauma = Trauma.None;
+          this.playerState.hop = true;
+          this.playerState.move = playerMove;
+          break;
+        case MoveOutcome.NoOp:
+          this.cameraState.shake = Trauma.None;
+          this.playerState.trauma = Trauma.None;
+          this.playerState.hop = false;
+          break;
+        case MoveOutcome.Bumped:
+          this.bump(delta);
+          break;
+        case MoveOutcome.End:
+          this.endGame();
+          break;
+      }
+    } catch (error) {
+      switch (error as FlashlightError) {
+        case FlashlightError.IllegalMove:
+        case FlashlightError.OutOfBounds:
+          this.bump(delta);
+          break;
+        case FlashlightError.GameOver:
+          this.endGame();
+          break;
+        default:
+          console.warn(
+            `Move refused: ${FlashlightError[error as FlashlightError]}`,
+          );
+      }
+    }
+
+    // the first player runs the monster, everyone else gets its moves as deltas
+    if (this.engine.player_index === 0) {
+      try {
+        // the monster attacking the player shows up as a bump
+        if (this.engine.do_move_enemy() === MoveOutcome.End) {
+          this.endGame();
+        }
+      } catch (error) {
+        console.warn(
+          `Monster move refused: ${FlashlightError[error as FlashlightError]}`,
+        );
       }
-      case MoveOutcome.Advance:
-        this.cameraState.shake = Trauma.None;
-        this.playerState.trauma = Trauma.None;
-        this.playerState.hop = true;
-        this.playerState.move = playerMove;
-        break;
-      case MoveOutcome.NoOp:
-        this.cameraState.shake = Trauma.None;
-        this.playerState.trauma = Trauma.None;
-        this.playerState.hop = false;
-        break;
-      case MoveOutcome.End:
-        this.cameraState.shake = Trauma.None;
-        this.cameraState.move = Pan.Drama;
-        this.playerState.trauma = Trauma.None;
-        this.playerState.hop = false;
-        this.uiState.showUI = false;
-        this.gameState.isGameOver = true;
-        break;
     }
 
-    this.engine.do_move_enemy();
     await this.tick();
   };
 
+  private bump(delta: readonly [number, number]) {
+    this.playerState.trauma =
+      delta[0] < 0
+        ? Trauma.TraumaL
+        : delta[0] > 0
+          ? Trauma.TraumaR
+          : delta[1] < 0
+            ? Trauma.TraumaU
+            : Trauma.TraumaD;
+    this.playerState.hop = false;
+    this.playerState.move = PlayerMove.None;
+
+    this.cameraState.shake = this.playerState.trauma;
+  }
+
+  private endGame() {
+    if (this.gameState.isGameOver) return;
+
+    this.cameraState.shake = Trauma.None;
+    this.cameraState.move = Pan.Drama;
+    this.playerState.trauma = Trauma.None;
+    this.playerState.hop = false;
+    this.uiState.showUI = false;
+    this.gameState.isGameOver = true;
+    // only players get to report how it went
+    if (this.role !== 'Player') return;
+
+    this.connectionManager.reportOutcome(
+      this.engine.player_poise > 0 ? 'Won' : 'Lost',
+    );
+  }
+
   private async tick() {
     if (!this.map) {
       return;
     }
 
     this.engine.compute_visibility();
+    this.uiState.batteryEl.textContent = `${this.engine.battery}%`;
     const mapState = this.engine.get_clipped_map_state();
     this.renderer.updateRendererState({
       gameMap: {
@@ -662,6 +904,7 @@ export class GameState {
         viewWidth: this.map?.viewWidth,
       },
       visibilityState: Array.from(this.engine.visibility_state),
+      tileVisibility: this.engine.get_clipped_tile_visibility(),
       particleState: [null, null],
       playerCellIdx: this.engine.map_metadata.player_cell_idx,
       playerAnimationState: {
@@ -672,13 +915,22 @@ export class GameState {
     await this.renderer.render();
   }
 
-  private async toggleFlashlight({ value }: { value: boolean }) {
-    this.playerState.isFlashlightOn = !value;
-    this.uiState.flashlightButtonEl.textContent = value
-      ? FlashlightGlyphs.Off
-      : FlashlightGlyphs.On;
+  private onFlashlightPress = async (e: Event) => {
+    e.preventDefault();
+    if (this.role !== 'Player') return;
 
-    this.uiState.flashlightButtonEl.classList.add('pointer-none');
+    await this.toggleFlashlight({ value: this.playerState.isFlashlightOn });
+  };
+
+  private async toggleFlashlight({ value }: { value: boolean }) {
+    // the engine owns the flashlight, before the game starts only the UI flips
+    this.playerState.isFlashlightOn = this.flashlight
+      ? this.flashlight.toggle_flashlight()
+      : !value;
+    this.uiState.flashlightButtonEl.textContent = this.playerState
+      .isFlashlightOn
+      ? FlashlightGlyphs.On
+      : FlashlightGlyphs.Off;
 
     await this.tick();
   }
@@ -1,6 +1,8 @@
 export const StorageKeysEnum = {
   ANNOUNCEMENT: 'announcement',
   MAP: 'map',
+  RESUME_TOKEN: 'resumeToken',
+  REPORT_TOKEN: 'reportToken',
 } as const;
 
 type StorageKey = (typeof StorageKeysEnum)[keyof typeof StorageKeysEnum];